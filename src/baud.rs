@@ -0,0 +1,139 @@
+use std::io;
+
+use termios::BaudRate;
+use termios::BaudRate::*;
+
+use ffi::{unsupported, self};
+use {Direction, SerialPort};
+
+/// How much (in percent) the effective baud rate may deviate from the requested one
+const TOLERANCE: u32 = 2;
+
+/// Standard baud rates and their frequency in Hz
+const STANDARD_RATES: &'static [(BaudRate, u32); 19] = &[
+    (B0, 0),
+    (B50, 50),
+    (B75, 75),
+    (B110, 110),
+    (B134, 134),
+    (B150, 150),
+    (B200, 200),
+    (B300, 300),
+    (B600, 600),
+    (B1200, 1200),
+    (B1800, 1800),
+    (B2400, 2400),
+    (B4800, 4800),
+    (B9600, 9600),
+    (B19200, 19200),
+    (B38400, 38400),
+    (B57600, 57600),
+    (B115200, 115200),
+    (B230400, 230400),
+];
+
+impl SerialPort {
+    /// Returns the input and output baud rates in Hz
+    ///
+    /// Unlike `baud_rate()`, this method also reports non-standard baud rates.
+    pub fn baud_rate_hz(&self) -> io::Result<(u32, u32)> {
+        match self.fetch2() {
+            Err(ref e) if unsupported(e) => {},
+            result => return result.map(|termios| (termios.c_ispeed, termios.c_ospeed)),
+        }
+
+        self.baud_rate().map(|(input, output)| (hz(input), hz(output)))
+    }
+
+    /// Changes the baud rate of the input/output or both directions to an arbitrary `rate` in Hz
+    ///
+    /// If the driver doesn't support non-standard baud rates, the nearest standard baud rate is
+    /// used instead. An error is returned if the effective baud rate deviates more than 2% from
+    /// the requested `rate`.
+    pub fn set_baud_rate_custom(&mut self, direction: Direction, rate: u32) -> io::Result<()> {
+        match self.fetch2() {
+            Err(ref e) if unsupported(e) => {},
+            Err(e) => return Err(e),
+            Ok(mut termios) => {
+                match direction {
+                    Direction::Both => {
+                        termios.c_cflag &= !(ffi::CBAUD | ffi::CBAUD << ffi::IBSHIFT);
+                        termios.c_cflag |= ffi::BOTHER | ffi::BOTHER << ffi::IBSHIFT;
+                        termios.c_ispeed = rate;
+                        termios.c_ospeed = rate;
+                    },
+                    Direction::Input => {
+                        termios.c_cflag &= !(ffi::CBAUD << ffi::IBSHIFT);
+                        termios.c_cflag |= ffi::BOTHER << ffi::IBSHIFT;
+                        termios.c_ispeed = rate;
+                    },
+                    Direction::Output => {
+                        termios.c_cflag &= !ffi::CBAUD;
+                        termios.c_cflag |= ffi::BOTHER;
+                        termios.c_ospeed = rate;
+                    },
+                }
+
+                match self.update2(&termios) {
+                    Err(ref e) if unsupported(e) => {},
+                    Err(e) => return Err(e),
+                    Ok(()) => return self.check_baud_rate(direction, rate),
+                }
+            },
+        }
+
+        try!(self.set_baud_rate(direction, nearest(rate)));
+        self.check_baud_rate(direction, rate)
+    }
+
+    /// Checks that the effective baud rate is within the tolerance of the requested `rate`
+    fn check_baud_rate(&self, direction: Direction, rate: u32) -> io::Result<()> {
+        let (input, output) = try!(self.baud_rate_hz());
+
+        let ok = match direction {
+            Direction::Both => within_tolerance(input, rate) && within_tolerance(output, rate),
+            Direction::Input => within_tolerance(input, rate),
+            Direction::Output => within_tolerance(output, rate),
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the device doesn't support a baud rate close enough to the requested one",
+            ))
+        }
+    }
+}
+
+/// Returns the frequency of a standard baud rate
+pub fn hz(rate: BaudRate) -> u32 {
+    STANDARD_RATES.iter().find(|&&(r, _)| r == rate).map(|&(_, hz)| hz).unwrap_or(0)
+}
+
+/// Returns the standard baud rate of exactly `rate` Hz, if any
+pub fn standard(rate: u32) -> Option<BaudRate> {
+    STANDARD_RATES.iter().find(|&&(_, hz)| hz == rate).map(|&(r, _)| r)
+}
+
+/// Returns the standard baud rate closest to `rate`
+pub fn nearest(rate: u32) -> BaudRate {
+    let mut best = (B0, 0);
+
+    for &(r, hz) in STANDARD_RATES {
+        if distance(hz, rate) < distance(best.1, rate) {
+            best = (r, hz);
+        }
+    }
+
+    best.0
+}
+
+fn distance(a: u32, b: u32) -> u32 {
+    if a > b { a - b } else { b - a }
+}
+
+fn within_tolerance(actual: u32, requested: u32) -> bool {
+    distance(actual, requested) as u64 * 100 <= requested as u64 * TOLERANCE as u64
+}
@@ -0,0 +1,696 @@
+//! Recording and replaying serial sessions
+//!
+//! A `RecordingPort` logs the traffic and the configuration changes of a port to a capture,
+//! which `ReplayPort` can play back later as a mock device, e.g. to reproduce in a unit test a
+//! bug seen in the field. The traffic can also be exported to Wireshark, see `PcapWriter`.
+//!
+//! # Capture format
+//!
+//! A capture starts with a 16 byte header: the `SERCAP` magic, a zero byte, the format version
+//! (`1`), and the wall-clock time at which the recording started, in microseconds since the
+//! Unix epoch, as a little endian `u64`.
+//!
+//! Then come the records. Each record starts with its kind (`0` for read data, `1` for written
+//! data, `2` for a configuration change) and the time elapsed since the start of the recording,
+//! in microseconds, as a LEB128 varint. Data records continue with the length of the data, as a
+//! varint, and the data itself. Configuration change records continue with the encoded change.
+
+use std::io::{ErrorKind, Read, Write, self};
+use std::time::Duration;
+
+use baud;
+use clock;
+use {BaudRate, BlockingMode, ConfigChange, DataBits, Direction, FlowControl, MockPort, Parity};
+use {Serial, SoftwareFlowControl, StopBits};
+
+const MAGIC: &'static [u8; 7] = b"SERCAP\0";
+const VERSION: u8 = 1;
+
+const CONFIG: u8 = 2;
+const READ: u8 = 0;
+const WRITE: u8 = 1;
+
+/// Something that crossed a serial port
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    /// The port was reconfigured
+    Config(ConfigChange),
+    /// Data was read from the port
+    Read(Vec<u8>),
+    /// Data was written to the port
+    Write(Vec<u8>),
+}
+
+/// Destination of the records of a `RecordingPort`
+pub trait CaptureSink {
+    /// Stores a `record` that happened `time` after the start of the recording
+    fn write_record(&mut self, time: Duration, record: &Record) -> io::Result<()>;
+}
+
+/// Writes records to a capture
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the capture header, with `start` as the wall-clock time since the Unix epoch at
+    /// which the recording started
+    pub fn new(mut writer: W, start: Duration) -> io::Result<CaptureWriter<W>> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        put_u64(&mut header, micros(start));
+        try!(writer.write_all(&header));
+
+        Ok(CaptureWriter {
+            writer: writer,
+        })
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> CaptureSink for CaptureWriter<W> {
+    fn write_record(&mut self, time: Duration, record: &Record) -> io::Result<()> {
+        let mut buf = Vec::new();
+
+        match *record {
+            Record::Config(ref change) => {
+                buf.push(CONFIG);
+                put_varint(&mut buf, micros(time));
+                encode_change(&mut buf, change);
+            },
+            Record::Read(ref data) => {
+                buf.push(READ);
+                put_varint(&mut buf, micros(time));
+                put_data(&mut buf, data);
+            },
+            Record::Write(ref data) => {
+                buf.push(WRITE);
+                put_varint(&mut buf, micros(time));
+                put_data(&mut buf, data);
+            },
+        }
+
+        self.writer.write_all(&buf)
+    }
+}
+
+/// Reads the records of a capture
+///
+/// Iterating yields each record along with the time it happened, relative to the start of the
+/// recording.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    start: Duration,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the capture header
+    pub fn new(mut reader: R) -> io::Result<CaptureReader<R>> {
+        let mut header = [0; 16];
+        try!(read_full(&mut reader, &mut header));
+
+        if &header[..7] != &MAGIC[..] || header[7] != VERSION {
+            return Err(invalid("not a capture, or an unsupported version"))
+        }
+
+        let mut start = &header[8..];
+        let start = try!(get_u64(&mut start));
+
+        Ok(CaptureReader {
+            reader: reader,
+            start: Duration::microseconds(start as i64),
+        })
+    }
+
+    /// Returns the wall-clock time since the Unix epoch at which the recording started
+    pub fn start_time(&self) -> Duration {
+        self.start
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<(Duration, Record)>> {
+        let mut kind = [0];
+
+        // Running out of data between records is the clean end of the capture
+        loop {
+            match self.reader.read(&mut kind) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+            }
+        }
+
+        let time = Duration::microseconds(try!(get_varint(&mut self.reader)) as i64);
+        let record = match kind[0] {
+            CONFIG => Record::Config(try!(decode_change(&mut self.reader))),
+            READ | WRITE => {
+                let len = try!(get_varint(&mut self.reader));
+                let mut data = Vec::new();
+
+                // A corrupted length mustn't make us allocate more than the capture holds
+                try!((&mut self.reader).take(len).read_to_end(&mut data));
+                if data.len() as u64 != len {
+                    return Err(invalid("truncated capture"))
+                }
+
+                if kind[0] == READ { Record::Read(data) } else { Record::Write(data) }
+            },
+            _ => return Err(invalid("unknown record kind")),
+        };
+
+        Ok(Some((time, record)))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<(Duration, Record)>;
+
+    fn next(&mut self) -> Option<io::Result<(Duration, Record)>> {
+        match self.next_record() {
+            Err(e) => Some(Err(e)),
+            Ok(record) => record.map(Ok),
+        }
+    }
+}
+
+/// A serial port that records its traffic and configuration changes
+///
+/// The records go to a `CaptureSink`, by default a capture in this library's format. Timestamps
+/// come from a monotonic clock. Configuration changes made through `get_mut()` are not
+/// recorded. If storing a record fails, the error is returned by the `read()`/`write()` call or
+/// the setter that was being recorded, even though the operation itself succeeded.
+pub struct RecordingPort<P: Serial, S: CaptureSink> {
+    port: P,
+    sink: S,
+    start: Duration,
+}
+
+impl<P: Serial, W: Write> RecordingPort<P, CaptureWriter<W>> {
+    /// Starts recording the session of `port` to a capture written to `writer`
+    pub fn new(port: P, writer: W) -> io::Result<RecordingPort<P, CaptureWriter<W>>> {
+        let capture = try!(CaptureWriter::new(writer, clock::realtime()));
+
+        Ok(RecordingPort::with_sink(port, capture))
+    }
+}
+
+impl<P: Serial, S: CaptureSink> RecordingPort<P, S> {
+    /// Starts recording the session of `port` to `sink`
+    pub fn with_sink(port: P, sink: S) -> RecordingPort<P, S> {
+        RecordingPort {
+            port: port,
+            sink: sink,
+            start: clock::monotonic(),
+        }
+    }
+
+    /// Returns a reference to the recorded port
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the recorded port
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Stops recording, returns the port and the sink
+    pub fn into_inner(self) -> (P, S) {
+        (self.port, self.sink)
+    }
+
+    fn record(&mut self, record: Record) -> io::Result<()> {
+        let time = clock::monotonic() - self.start;
+
+        self.sink.write_record(time, &record)
+    }
+
+    fn record_change(&mut self, result: io::Result<()>, change: ConfigChange) -> io::Result<()> {
+        try!(result);
+        self.record(Record::Config(change))
+    }
+}
+
+impl<P: Serial, S: CaptureSink> Read for RecordingPort<P, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.port.read(buf));
+
+        if n != 0 {
+            try!(self.record(Record::Read(buf[..n].to_vec())));
+        }
+
+        Ok(n)
+    }
+}
+
+impl<P: Serial, S: CaptureSink> Write for RecordingPort<P, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.port.write(buf));
+
+        if n != 0 {
+            try!(self.record(Record::Write(buf[..n].to_vec())));
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: Serial, S: CaptureSink> Serial for RecordingPort<P, S> {
+    fn baud_rate(&self) -> io::Result<(BaudRate, BaudRate)> {
+        self.port.baud_rate()
+    }
+
+    fn blocking_mode(&self) -> io::Result<BlockingMode> {
+        self.port.blocking_mode()
+    }
+
+    fn data_bits(&self) -> io::Result<DataBits> {
+        self.port.data_bits()
+    }
+
+    fn flow_control(&self) -> io::Result<FlowControl> {
+        self.port.flow_control()
+    }
+
+    fn parity(&self) -> io::Result<Parity> {
+        self.port.parity()
+    }
+
+    fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()> {
+        let result = self.port.set_baud_rate(direction, rate);
+        self.record_change(result, ConfigChange::BaudRate(direction, rate))
+    }
+
+    fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()> {
+        let result = self.port.set_blocking_mode(mode);
+        self.record_change(result, ConfigChange::BlockingMode(mode))
+    }
+
+    fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()> {
+        let result = self.port.set_data_bits(bits);
+        self.record_change(result, ConfigChange::DataBits(bits))
+    }
+
+    fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()> {
+        let result = self.port.set_flow_control(flow);
+        self.record_change(result, ConfigChange::FlowControl(flow))
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
+        let result = self.port.set_parity(parity);
+        self.record_change(result, ConfigChange::Parity(parity))
+    }
+
+    fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()> {
+        let result = self.port.set_stop_bits(bits);
+        self.record_change(result, ConfigChange::StopBits(bits))
+    }
+
+    /// Changes the timeout, the change is recorded on a best effort basis
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.port.set_timeout(timeout);
+        let _ = self.record(Record::Config(ConfigChange::Timeout(timeout)));
+    }
+
+    fn stop_bits(&self) -> io::Result<StopBits> {
+        self.port.stop_bits()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.port.timeout()
+    }
+}
+
+/// A mock device that plays back a capture
+///
+/// The recorded reads become data to be read, and the recorded writes become expected writes,
+/// see `MockPort`. `verify()` also checks that the port was reconfigured as in the recording.
+pub struct ReplayPort {
+    changes: Vec<ConfigChange>,
+    mock: MockPort,
+}
+
+impl ReplayPort {
+    /// Loads a capture
+    ///
+    /// With `timing`, the recorded data is only available for reading after the same delay as
+    /// in the recording, otherwise the capture is played back as fast as possible.
+    pub fn new<R: Read>(capture: R, timing: bool) -> io::Result<ReplayPort> {
+        let mut changes = Vec::new();
+        let mut mock = MockPort::new();
+        let mut last = Duration::zero();
+
+        for record in try!(CaptureReader::new(capture)) {
+            let (time, record) = try!(record);
+
+            match record {
+                Record::Config(change) => changes.push(change),
+                Record::Read(data) => {
+                    if timing && time > last {
+                        mock.push_delay(time - last);
+                    }
+
+                    mock.push_read(&data);
+                },
+                Record::Write(data) => {
+                    mock.expect_write(&data);
+                },
+            }
+
+            last = time;
+        }
+
+        Ok(ReplayPort {
+            changes: changes,
+            mock: mock,
+        })
+    }
+
+    /// Returns the underlying mock device
+    pub fn get_ref(&self) -> &MockPort {
+        &self.mock
+    }
+
+    /// Returns the underlying mock device, e.g. to inject errors
+    pub fn get_mut(&mut self) -> &mut MockPort {
+        &mut self.mock
+    }
+
+    /// Panics if some of the capture hasn't been played, or if the port wasn't reconfigured as
+    /// in the recording
+    pub fn verify(&self) {
+        self.mock.verify();
+
+        if self.mock.changes() != &self.changes[..] {
+            panic!("expected the changes {:?} - got {:?}", self.changes, self.mock.changes())
+        }
+    }
+}
+
+impl Read for ReplayPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.mock.read(buf)
+    }
+}
+
+impl Write for ReplayPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.mock.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.mock.flush()
+    }
+}
+
+impl Serial for ReplayPort {
+    fn baud_rate(&self) -> io::Result<(BaudRate, BaudRate)> {
+        self.mock.baud_rate()
+    }
+
+    fn blocking_mode(&self) -> io::Result<BlockingMode> {
+        self.mock.blocking_mode()
+    }
+
+    fn data_bits(&self) -> io::Result<DataBits> {
+        self.mock.data_bits()
+    }
+
+    fn flow_control(&self) -> io::Result<FlowControl> {
+        self.mock.flow_control()
+    }
+
+    fn parity(&self) -> io::Result<Parity> {
+        self.mock.parity()
+    }
+
+    fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()> {
+        self.mock.set_baud_rate(direction, rate)
+    }
+
+    fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()> {
+        self.mock.set_blocking_mode(mode)
+    }
+
+    fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()> {
+        self.mock.set_data_bits(bits)
+    }
+
+    fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()> {
+        self.mock.set_flow_control(flow)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
+        self.mock.set_parity(parity)
+    }
+
+    fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()> {
+        self.mock.set_stop_bits(bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.mock.set_timeout(timeout)
+    }
+
+    fn stop_bits(&self) -> io::Result<StopBits> {
+        self.mock.stop_bits()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.mock.timeout()
+    }
+}
+
+fn encode_change(buf: &mut Vec<u8>, change: &ConfigChange) {
+    match *change {
+        ConfigChange::BaudRate(direction, rate) => {
+            buf.push(0);
+            buf.push(match direction {
+                Direction::Both => 0,
+                Direction::Input => 1,
+                Direction::Output => 2,
+            });
+            put_varint(buf, baud::hz(rate) as u64);
+        },
+        ConfigChange::BlockingMode(mode) => {
+            buf.push(1);
+            buf.push(mode.bytes);
+            buf.push(mode.deciseconds);
+        },
+        ConfigChange::DataBits(bits) => {
+            buf.push(2);
+            buf.push(match bits {
+                DataBits::Five => 5,
+                DataBits::Six => 6,
+                DataBits::Seven => 7,
+                DataBits::Eight => 8,
+            });
+        },
+        ConfigChange::FlowControl(flow) => {
+            buf.push(3);
+
+            match flow {
+                FlowControl::Both(software) => {
+                    buf.push(3);
+                    put_software(buf, software);
+                },
+                FlowControl::Hardware => buf.push(1),
+                FlowControl::None => buf.push(0),
+                FlowControl::Software(software) => {
+                    buf.push(2);
+                    put_software(buf, software);
+                },
+            }
+        },
+        ConfigChange::Parity(parity) => {
+            buf.push(4);
+            buf.push(match parity {
+                Parity::Even => 2,
+                Parity::Mark => 3,
+                Parity::None => 0,
+                Parity::Odd => 1,
+                Parity::Space => 4,
+            });
+        },
+        ConfigChange::StopBits(bits) => {
+            buf.push(5);
+            buf.push(match bits {
+                StopBits::One => 1,
+                StopBits::Two => 2,
+            });
+        },
+        ConfigChange::Timeout(timeout) => {
+            buf.push(6);
+
+            match timeout {
+                None => buf.push(0),
+                Some(timeout) => {
+                    buf.push(1);
+                    put_varint(buf, micros(timeout));
+                },
+            }
+        },
+    }
+}
+
+fn decode_change<R: Read>(reader: &mut R) -> io::Result<ConfigChange> {
+    let change = match try!(get_u8(reader)) {
+        0 => {
+            let direction = match try!(get_u8(reader)) {
+                0 => Direction::Both,
+                1 => Direction::Input,
+                2 => Direction::Output,
+                _ => return Err(invalid("unknown direction")),
+            };
+            let rate = try!(get_varint(reader));
+
+            ConfigChange::BaudRate(direction, baud::nearest(rate as u32))
+        },
+        1 => {
+            let bytes = try!(get_u8(reader));
+            let deciseconds = try!(get_u8(reader));
+
+            ConfigChange::BlockingMode(BlockingMode { bytes: bytes, deciseconds: deciseconds })
+        },
+        2 => ConfigChange::DataBits(match try!(get_u8(reader)) {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            _ => return Err(invalid("unknown number of data bits")),
+        }),
+        3 => ConfigChange::FlowControl(match try!(get_u8(reader)) {
+            0 => FlowControl::None,
+            1 => FlowControl::Hardware,
+            2 => FlowControl::Software(try!(get_software(reader))),
+            3 => FlowControl::Both(try!(get_software(reader))),
+            _ => return Err(invalid("unknown flow control")),
+        }),
+        4 => ConfigChange::Parity(match try!(get_u8(reader)) {
+            0 => Parity::None,
+            1 => Parity::Odd,
+            2 => Parity::Even,
+            3 => Parity::Mark,
+            4 => Parity::Space,
+            _ => return Err(invalid("unknown parity")),
+        }),
+        5 => ConfigChange::StopBits(match try!(get_u8(reader)) {
+            1 => StopBits::One,
+            2 => StopBits::Two,
+            _ => return Err(invalid("unknown number of stop bits")),
+        }),
+        6 => ConfigChange::Timeout(match try!(get_u8(reader)) {
+            0 => None,
+            _ => Some(Duration::microseconds(try!(get_varint(reader)) as i64)),
+        }),
+        _ => return Err(invalid("unknown configuration change")),
+    };
+
+    Ok(change)
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.num_microseconds().unwrap_or(0) as u64
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn put_u64(buf: &mut Vec<u8>, mut n: u64) {
+    for _ in 0..8 {
+        buf.push(n as u8);
+        n >>= 8;
+    }
+}
+
+fn get_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut n = 0;
+
+    for i in 0..8 {
+        n |= (try!(get_u8(reader)) as u64) << (8 * i);
+    }
+
+    Ok(n)
+}
+
+/// Appends the length of `data`, then `data`
+fn put_data(buf: &mut Vec<u8>, data: &[u8]) {
+    put_varint(buf, data.len() as u64);
+    buf.extend(data.iter().cloned());
+}
+
+/// Appends `n` as a LEB128 varint
+fn put_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+
+    buf.push(n as u8);
+}
+
+fn get_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut n = 0;
+
+    for i in 0..10 {
+        let byte = try!(get_u8(reader));
+        n |= ((byte & 0x7F) as u64) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(n)
+        }
+    }
+
+    Err(invalid("varint too long"))
+}
+
+fn put_software(buf: &mut Vec<u8>, software: SoftwareFlowControl) {
+    buf.push(software.any as u8 | (software.input as u8) << 1 | (software.output as u8) << 2);
+    buf.push(software.xoff);
+    buf.push(software.xon);
+}
+
+fn get_software<R: Read>(reader: &mut R) -> io::Result<SoftwareFlowControl> {
+    let flags = try!(get_u8(reader));
+    let xoff = try!(get_u8(reader));
+    let xon = try!(get_u8(reader));
+
+    Ok(SoftwareFlowControl {
+        any: flags & 1 != 0,
+        input: flags & 2 != 0,
+        output: flags & 4 != 0,
+        xoff: xoff,
+        xon: xon,
+    })
+}
+
+fn get_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    try!(read_full(reader, &mut byte));
+    Ok(byte[0])
+}
+
+fn read_full<R: Read>(reader: &mut R, mut buf: &mut [u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match reader.read(buf) {
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+            Ok(0) => return Err(invalid("truncated capture")),
+            Ok(n) => {
+                let tmp = buf;
+                buf = &mut tmp[n..];
+            },
+        }
+    }
+
+    Ok(())
+}
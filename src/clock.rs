@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use libc::c_int;
+
+use ffi;
+
+/// Time elapsed since an arbitrary point in the past, unaffected by changes of the system time
+pub fn monotonic() -> Duration {
+    now(ffi::CLOCK_MONOTONIC)
+}
+
+/// Time elapsed since the Unix epoch
+pub fn realtime() -> Duration {
+    now(ffi::CLOCK_REALTIME)
+}
+
+fn now(clock: c_int) -> Duration {
+    let mut ts = ffi::timespec { tv_sec: 0, tv_nsec: 0 };
+    let ptr: *mut ffi::timespec = &mut ts;
+
+    // Can only fail if the clock doesn't exist
+    unsafe {
+        ffi::clock_gettime(clock, ptr);
+    }
+
+    Duration::seconds(ts.tv_sec as i64) + Duration::nanoseconds(ts.tv_nsec as i64)
+}
@@ -0,0 +1,212 @@
+use std::fs::{File, self};
+use std::io::{Read, self};
+use std::path::{Component, Path, PathBuf};
+
+/// Kind of hardware behind a serial port
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortType {
+    /// A PCI card, e.g. a multiport serial board
+    Pci,
+    /// A port built into the system, e.g. a motherboard UART
+    Platform,
+    /// A legacy (BSD-style) pseudo terminal
+    Pty,
+    /// A bus this library doesn't know about
+    Unknown,
+    /// A USB adapter, e.g. a FTDI or CP210x cable, or a CDC ACM device
+    Usb,
+}
+
+/// A serial port present in the system
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortInfo {
+    /// Name of the kernel driver, e.g. `ftdi_sio`
+    pub driver: Option<String>,
+    /// Path to the device node, e.g. `/dev/ttyUSB0`
+    pub path: PathBuf,
+    /// Kind of hardware behind the port
+    pub port_type: PortType,
+    /// Identification of the USB device, for ports of the `Usb` type
+    pub usb: Option<UsbInfo>,
+}
+
+/// Identification of the USB device behind a serial port
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsbInfo {
+    /// Number of the USB interface that implements the port, multiport adapters expose one
+    /// interface per port
+    pub interface: Option<u8>,
+    /// Manufacturer string
+    pub manufacturer: Option<String>,
+    /// Product ID
+    pub pid: u16,
+    /// Product string
+    pub product: Option<String>,
+    /// Serial number string, unique for each adapter if the manufacturer bothered to program it
+    pub serial_number: Option<String>,
+    /// Vendor ID
+    pub vid: u16,
+}
+
+impl UsbInfo {
+    /// Whether this is the USB device with the given IDs and, if specified, `serial_number`
+    pub fn matches(&self, vid: u16, pid: u16, serial_number: Option<&str>) -> bool {
+        self.vid == vid && self.pid == pid && match serial_number {
+            None => true,
+            Some(serial_number) => {
+                self.serial_number.as_ref().map(|s| &s[..]) == Some(serial_number)
+            },
+        }
+    }
+}
+
+/// Returns the serial ports present in the system, sorted by path
+///
+/// Virtual consoles and other ttys not backed by a serial device are left out.
+pub fn available_ports() -> io::Result<Vec<PortInfo>> {
+    ports(Path::new("/sys"), Path::new("/dev"))
+}
+
+/// Lists the serial ports registered in the `sys` sysfs tree, whose device nodes live in `dev`
+pub fn ports(sys: &Path, dev: &Path) -> io::Result<Vec<PortInfo>> {
+    let mut ports = Vec::new();
+
+    for entry in try!(fs::read_dir(&sys.join("class").join("tty"))) {
+        let path = try!(entry).path();
+
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if let Some(info) = port_info(sys, dev, name) {
+                ports.push(info);
+            }
+        }
+    }
+
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(ports)
+}
+
+/// Looks up the tty called `name` in the `sys` sysfs tree
+///
+/// Returns `None` if the tty is not a serial port.
+pub fn port_info(sys: &Path, dev: &Path, name: &str) -> Option<PortInfo> {
+    let device = sys.join("class").join("tty").join(name).join("device");
+    let driver = link_name(&device.join("driver"));
+
+    let port_type = match link_name(&device.join("subsystem")) {
+        // Virtual consoles, `/dev/tty`, `/dev/console`, etc. are not backed by a device
+        None => if is_pty(name) { PortType::Pty } else { return None },
+        Some(subsystem) => match &subsystem[..] {
+            "pci" => PortType::Pci,
+            "amba" | "platform" | "pnp" => PortType::Platform,
+            "usb" | "usb-serial" => PortType::Usb,
+            _ => PortType::Unknown,
+        },
+    };
+
+    // The 8250 driver registers placeholder ports whether or not the hardware is present
+    if driver.as_ref().map(|driver| &driver[..]) == Some("serial8250") {
+        return None
+    }
+
+    let usb = match port_type {
+        PortType::Usb => {
+            follow(&sys.join("class").join("tty").join(name)).
+                and_then(|tty| follow(&tty.join("device"))).
+                and_then(|device| usb_info(&device))
+        },
+        _ => None,
+    };
+
+    Some(PortInfo {
+        driver: driver,
+        path: dev.join(name),
+        port_type: port_type,
+        usb: usb,
+    })
+}
+
+/// Reads the USB attributes of the first USB device found among the ancestors of `device`
+fn usb_info(device: &Path) -> Option<UsbInfo> {
+    let mut interface = None;
+    let mut dir = Some(device);
+
+    while let Some(path) = dir {
+        if let Some(vid) = read_hex(&path.join("idVendor")) {
+            return Some(UsbInfo {
+                interface: interface,
+                manufacturer: read_attr(&path.join("manufacturer")),
+                pid: read_hex(&path.join("idProduct")).unwrap_or(0) as u16,
+                product: read_attr(&path.join("product")),
+                serial_number: read_attr(&path.join("serial")),
+                vid: vid as u16,
+            })
+        }
+
+        // The interface is the child of the USB device
+        interface = read_hex(&path.join("bInterfaceNumber")).map(|n| n as u8);
+        dir = path.parent();
+    }
+
+    None
+}
+
+/// Resolves the `link` symlink, assuming that its parent directories are not symlinks
+fn follow(link: &Path) -> Option<PathBuf> {
+    fs::read_link(link).ok().and_then(|target| {
+        link.parent().map(|parent| normalize(&parent.join(&target)))
+    })
+}
+
+/// Lexically removes the `.` and `..` components of `path`
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component.as_os_str()),
+        }
+    }
+
+    normalized
+}
+
+/// Reads a sysfs attribute
+fn read_attr(path: &Path) -> Option<String> {
+    let mut contents = String::new();
+
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+        Err(_) => None,
+        Ok(_) => Some(contents.trim().to_string()),
+    }
+}
+
+/// Reads a sysfs attribute formatted as an hexadecimal number
+fn read_hex(path: &Path) -> Option<u32> {
+    read_attr(path).and_then(|attr| u32::from_str_radix(&attr, 16).ok())
+}
+
+/// Whether `name` is a legacy pseudo terminal, e.g. `ptyp0` or `ttyp0`
+fn is_pty(name: &str) -> bool {
+    let bytes = name.as_bytes();
+
+    if bytes.len() != 5 || !(bytes.starts_with(b"pty") || bytes.starts_with(b"tty")) {
+        return false
+    }
+
+    match bytes[3] {
+        b'a'...b'e' | b'p'...b'z' => true,
+        _ => false,
+    }
+}
+
+/// Returns the file name of the target of the `link` symlink
+fn link_name(link: &Path) -> Option<String> {
+    fs::read_link(link).ok().and_then(|target| {
+        target.file_name().and_then(|name| name.to_str()).map(|name| name.to_string())
+    })
+}
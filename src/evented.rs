@@ -0,0 +1,284 @@
+//! Event loop support
+//!
+//! Instead of dedicating a thread to each serial port, several `AsyncPort`s can be registered
+//! with a `Reactor` and serviced from a single thread when they become ready.
+
+use std::fs::File;
+use std::io::{Read, Write, self};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Duration;
+
+use libc;
+
+use clock;
+use ffi;
+use poll;
+use {DataBits, Direction, FlowControl, Parity, PortSettings, SerialPort, StopBits};
+use termios::BaudRate;
+
+/// Readiness of a file descriptor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ready {
+    /// `read()` won't block
+    pub readable: bool,
+    /// `write()` won't block
+    pub writable: bool,
+}
+
+/// An `epoll` instance
+pub struct Reactor {
+    epoll: File,
+}
+
+impl Reactor {
+    /// Creates a new reactor with no registered file descriptors
+    pub fn new() -> io::Result<Reactor> {
+        let fd = try!(ffi::cvt(unsafe { ffi::epoll_create1(ffi::EPOLL_CLOEXEC) }));
+
+        Ok(Reactor {
+            epoll: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Stops watching `io`
+    pub fn deregister(&self, io: &AsRawFd) -> io::Result<()> {
+        let mut event = ffi::epoll_event { events: 0, data: 0 };
+
+        self.ctl(ffi::EPOLL_CTL_DEL, io.as_raw_fd(), &mut event)
+    }
+
+    /// Waits until any of the registered file descriptors becomes ready
+    ///
+    /// The `token` and the readiness of each ready file descriptor are appended to `events`.
+    /// Returns the number of appended events, which is zero if the `timeout` elapsed first.
+    /// Readiness is level triggered: a file descriptor is reported again by the next `poll` if
+    /// it's still ready.
+    pub fn poll(
+        &self,
+        events: &mut Vec<(u64, Ready)>,
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        const MAX_EVENTS: usize = 64;
+
+        let deadline = timeout.map(|timeout| clock::monotonic() + timeout);
+        let mut buf = vec![ffi::epoll_event { events: 0, data: 0 }; MAX_EVENTS];
+        let n;
+
+        loop {
+            // A signal restarts the wait with whatever is left of the `timeout`
+            let remaining = deadline.map(|deadline| deadline - clock::monotonic());
+            let ret = unsafe {
+                ffi::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    buf.as_mut_ptr(),
+                    MAX_EVENTS as libc::c_int,
+                    poll::millis(remaining),
+                )
+            };
+
+            match ffi::cvt(ret) {
+                Err(ref e) if e.raw_os_error() == Some(libc::EINTR) => continue,
+                Err(e) => return Err(e),
+                Ok(ret) => {
+                    n = ret as usize;
+                    break
+                },
+            }
+        }
+
+        for event in &buf[..n] {
+            // Errors and hang ups are reported by the next `read()`/`write()` call
+            let failed = event.events & (ffi::EPOLLERR | ffi::EPOLLHUP) != 0;
+
+            events.push((event.data, Ready {
+                readable: failed || event.events & ffi::EPOLLIN != 0,
+                writable: failed || event.events & ffi::EPOLLOUT != 0,
+            }));
+        }
+
+        Ok(n)
+    }
+
+    /// Starts watching `io` for the readiness selected by `interest`
+    ///
+    /// `token` identifies `io` in the events returned by `poll`.
+    pub fn register(&self, io: &AsRawFd, token: u64, interest: Ready) -> io::Result<()> {
+        let mut event = epoll_event(token, interest);
+
+        self.ctl(ffi::EPOLL_CTL_ADD, io.as_raw_fd(), &mut event)
+    }
+
+    /// Changes the `token` and the `interest` of an already registered `io`
+    pub fn reregister(&self, io: &AsRawFd, token: u64, interest: Ready) -> io::Result<()> {
+        let mut event = epoll_event(token, interest);
+
+        self.ctl(ffi::EPOLL_CTL_MOD, io.as_raw_fd(), &mut event)
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, event: &mut ffi::epoll_event) -> io::Result<()> {
+        try!(ffi::cvt(unsafe { ffi::epoll_ctl(self.epoll.as_raw_fd(), op, fd, event) }));
+        Ok(())
+    }
+}
+
+impl AsRawFd for Reactor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll.as_raw_fd()
+    }
+}
+
+/// A serial port in non-blocking mode, meant to be driven by an event loop
+///
+/// The setters of this type reconfigure the port without blocking. Some of the methods reachable
+/// through `get_mut()` block regardless of the non-blocking mode and would stall the event loop:
+/// `drain()`, `send_break()`, `write_address()`, `wait_for_modem_change()` and, while the RS-485
+/// mode is emulated (see `SerialPort::set_rs485()`), `write()`. `new()` rejects ports that
+/// emulate the RS-485 mode.
+pub struct AsyncPort {
+    port: SerialPort,
+}
+
+impl AsyncPort {
+    /// Switches `port` to non-blocking mode
+    ///
+    /// Fails with an error of kind `InvalidInput` if `port` emulates the RS-485 mode, as its
+    /// writes wait for the end of each transmission.
+    pub fn new(mut port: SerialPort) -> io::Result<AsyncPort> {
+        if port.rs485.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "writes block while the RS-485 mode is emulated",
+            ))
+        }
+
+        try!(port.set_nonblocking(true));
+        port.set_timeout(None);
+
+        Ok(AsyncPort {
+            port: port,
+        })
+    }
+
+    /// Returns a reference to the underlying serial port
+    pub fn get_ref(&self) -> &SerialPort {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying serial port
+    pub fn get_mut(&mut self) -> &mut SerialPort {
+        &mut self.port
+    }
+
+    /// Switches the serial port back to blocking mode and returns it
+    pub fn into_inner(mut self) -> io::Result<SerialPort> {
+        try!(self.port.set_nonblocking(false));
+        Ok(self.port)
+    }
+
+    /// Changes all the settings of the device at once
+    pub fn apply_settings(&mut self, settings: &PortSettings) -> io::Result<()> {
+        self.port.apply_settings(settings)
+    }
+
+    /// Changes the baud rate of the input/output or both directions
+    pub fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()> {
+        self.port.set_baud_rate(direction, rate)
+    }
+
+    /// Changes the number of data bits per character
+    pub fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()> {
+        self.port.set_data_bits(bits)
+    }
+
+    /// Drives the Data Terminal Ready line
+    pub fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.port.set_dtr(level)
+    }
+
+    /// Changes the flow control used by the device
+    pub fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()> {
+        self.port.set_flow_control(flow)
+    }
+
+    /// Changes the bit parity used by the device
+    pub fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
+        self.port.set_parity(parity)
+    }
+
+    /// Drives the Request To Send line
+    pub fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.port.set_rts(level)
+    }
+
+    /// Changes the number of stop bits per character
+    pub fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()> {
+        self.port.set_stop_bits(bits)
+    }
+
+    /// Returns all the settings of the device
+    pub fn settings(&self) -> io::Result<PortSettings> {
+        self.port.settings()
+    }
+
+    /// Reads data if any is available
+    ///
+    /// Returns `None` if the read would block, i.e. the port is not readable yet.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        would_block(self.port.read(buf))
+    }
+
+    /// Writes data if the device can accept it
+    ///
+    /// Returns `None` if the write would block, i.e. the port is not writable yet.
+    pub fn try_write(&mut self, buf: &[u8]) -> io::Result<Option<usize>> {
+        would_block(self.port.write(buf))
+    }
+}
+
+/// Fails with an error of kind `WouldBlock` if no data is available
+impl Read for AsyncPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+/// Fails with an error of kind `WouldBlock` if the device can't accept data
+impl Write for AsyncPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl AsRawFd for AsyncPort {
+    fn as_raw_fd(&self) -> RawFd {
+        self.port.as_raw_fd()
+    }
+}
+
+fn epoll_event(token: u64, interest: Ready) -> ffi::epoll_event {
+    let mut events = 0;
+
+    if interest.readable {
+        events |= ffi::EPOLLIN;
+    }
+
+    if interest.writable {
+        events |= ffi::EPOLLOUT;
+    }
+
+    ffi::epoll_event {
+        events: events,
+        data: token,
+    }
+}
+
+fn would_block(result: io::Result<usize>) -> io::Result<Option<usize>> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        result => result.map(Some),
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{Read, self};
+
+use termios::prelude::*;
+
+use {Parity, SerialPort};
+
+/// Something received by a serial port in marked input mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// A break condition
+    Break,
+    /// A correctly received byte
+    Data(u8),
+    /// A byte received with a framing error, its value is meaningless
+    FramingError,
+    /// A byte received with a parity error
+    ParityError(u8),
+}
+
+/// Decodes the escape sequences that `PARMRK` inserts in the input stream
+///
+/// The decoder keeps its state between calls to `decode`, so escape sequences split across
+/// several `read()` calls are handled correctly.
+pub struct Decoder {
+    state: State,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Not inside an escape sequence
+    Idle,
+    /// Got `\xFF`
+    Escape,
+    /// Got `\xFF\x00`
+    Mark,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            state: State::Idle,
+        }
+    }
+
+    /// Decodes `bytes` and appends the resulting events to `events`
+    ///
+    /// The kernel marks parity and framing errors in the same way, `parity` indicates whether
+    /// marked bytes should be reported as parity errors.
+    pub fn decode(&mut self, bytes: &[u8], parity: bool, events: &mut Vec<Event>) {
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (State::Idle, 0xFF) => State::Escape,
+                (State::Idle, byte) => {
+                    events.push(Event::Data(byte));
+                    State::Idle
+                },
+                // A literal `\xFF` is escaped as `\xFF\xFF`
+                (State::Escape, 0xFF) => {
+                    events.push(Event::Data(0xFF));
+                    State::Idle
+                },
+                (State::Escape, 0x00) => State::Mark,
+                // Not an escape sequence, only possible if `PARMRK` was enabled mid-stream
+                (State::Escape, byte) => {
+                    events.push(Event::Data(0xFF));
+                    events.push(Event::Data(byte));
+                    State::Idle
+                },
+                (State::Mark, 0x00) => {
+                    events.push(Event::Break);
+                    State::Idle
+                },
+                (State::Mark, byte) => {
+                    events.push(if parity {
+                        Event::ParityError(byte)
+                    } else {
+                        Event::FramingError
+                    });
+                    State::Idle
+                },
+            };
+        }
+    }
+}
+
+impl SerialPort {
+    /// Returns whether the marked input mode is enabled
+    pub fn marked_input(&self) -> io::Result<bool> {
+        self.fetch().map(|termios| termios.contains(input::Flag::PARMRK))
+    }
+
+    /// Decodes the input stream into `events`
+    ///
+    /// Only a single `read()` call is made, so the number of events returned may be zero if only
+    /// part of an escape sequence was received. Returns the number of events appended to
+    /// `events`.
+    ///
+    /// This method is meant to be used in marked input mode, where it's the only way to tell
+    /// corrupted bytes and received breaks apart from valid data. When parity checking is
+    /// enabled, framing errors are reported as parity errors. A break is indistinguishable from a
+    /// NUL byte received with a parity or framing error and is always reported as `Break`.
+    pub fn read_events(&mut self, events: &mut Vec<Event>) -> io::Result<usize> {
+        let parity = try!(self.parity()) != Parity::None;
+        let mut buf = [0; 256];
+        let n = try!(self.read(&mut buf));
+        let before = events.len();
+
+        self.decoder.decode(&buf[..n], parity, events);
+
+        Ok(events.len() - before)
+    }
+
+    /// Enables or disables the marked input mode
+    ///
+    /// In "raw" mode, parity errors go undetected, and both a received break condition and a byte
+    /// received with a framing error are read as a NUL byte. In marked input mode, parity
+    /// checking is enabled and the kernel marks breaks in the input stream with the
+    /// `\xFF\x00\x00` sequence, corrupted bytes with the `\xFF\x00` prefix, and escapes literal
+    /// `\xFF` bytes as `\xFF\xFF`. Use `read_events()` to decode the input stream in this mode.
+    pub fn set_marked_input(&mut self, enable: bool) -> io::Result<()> {
+        self.fetch().and_then(|mut termios| {
+            if enable {
+                termios.clear(input::Flag::BRKINT);
+                termios.clear(input::Flag::IGNBRK);
+                termios.clear(input::Flag::IGNPAR);
+                termios.clear(input::Flag::ISTRIP);
+                termios.set(input::Flag::INPCK);
+                termios.set(input::Flag::PARMRK);
+            } else {
+                termios.clear(input::Flag::INPCK);
+                termios.clear(input::Flag::PARMRK);
+            }
+
+            try!(self.update(termios));
+            self.decoder = Decoder::new();
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,222 @@
+//! Linux interfaces that are covered neither by the termios API nor by `std`
+
+#![allow(dead_code)]
+#![allow(non_camel_case_types)]
+
+use std::io;
+
+use libc::{c_int, c_long, c_short, c_uint, c_ulong, time_t, self};
+
+pub type cc_t = u8;
+pub type speed_t = c_uint;
+pub type tcflag_t = c_uint;
+
+pub const NCCS: usize = 19;
+
+/// `struct termios2` from `asm-generic/termbits.h`
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct termios2 {
+    pub c_iflag: tcflag_t,
+    pub c_oflag: tcflag_t,
+    pub c_cflag: tcflag_t,
+    pub c_lflag: tcflag_t,
+    pub c_line: cc_t,
+    pub c_cc: [cc_t; NCCS],
+    pub c_ispeed: speed_t,
+    pub c_ospeed: speed_t,
+}
+
+/// `struct epoll_event` from `sys/epoll.h`, which is packed on x86_64
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct epoll_event {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// `struct epoll_event` from `sys/epoll.h`
+#[cfg(not(target_arch = "x86_64"))]
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct epoll_event {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// `struct sockaddr_nl` from `linux/netlink.h`
+#[repr(C)]
+pub struct sockaddr_nl {
+    pub nl_family: u16,
+    pub nl_pad: u16,
+    pub nl_pid: u32,
+    pub nl_groups: u32,
+}
+
+/// `struct pollfd` from `poll.h`
+#[repr(C)]
+pub struct pollfd {
+    pub fd: c_int,
+    pub events: c_short,
+    pub revents: c_short,
+}
+
+/// `struct serial_icounter_struct` from `linux/serial.h`
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct serial_icounter_struct {
+    pub cts: c_int,
+    pub dsr: c_int,
+    pub rng: c_int,
+    pub dcd: c_int,
+    pub rx: c_int,
+    pub tx: c_int,
+    pub frame: c_int,
+    pub overrun: c_int,
+    pub parity: c_int,
+    pub brk: c_int,
+    pub buf_overrun: c_int,
+    pub reserved: [c_int; 9],
+}
+
+/// `struct serial_rs485` from `linux/serial.h`
+#[repr(C)]
+pub struct serial_rs485 {
+    pub flags: u32,
+    pub delay_rts_before_send: u32,
+    pub delay_rts_after_send: u32,
+    pub padding: [u32; 5],
+}
+
+/// `struct timespec` from `time.h`
+#[repr(C)]
+pub struct timespec {
+    pub tv_sec: time_t,
+    pub tv_nsec: c_long,
+}
+
+// c_cflag bits
+pub const BOTHER: tcflag_t = 0o010000;
+pub const CBAUD: tcflag_t = 0o010017;
+pub const IBSHIFT: usize = 16;
+
+// clocks
+pub const CLOCK_REALTIME: c_int = 0;
+pub const CLOCK_MONOTONIC: c_int = 1;
+
+// epoll
+pub const EPOLL_CLOEXEC: c_int = 0o2000000;
+pub const EPOLL_CTL_ADD: c_int = 1;
+pub const EPOLL_CTL_DEL: c_int = 2;
+pub const EPOLL_CTL_MOD: c_int = 3;
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+
+// fcntl commands and flags
+pub const F_GETFL: c_int = 3;
+pub const F_SETFL: c_int = 4;
+pub const F_DUPFD_CLOEXEC: c_int = 1030;
+pub const O_NONBLOCK: c_int = 0o4000;
+pub const O_CLOEXEC: c_int = 0o2000000;
+
+// flock operations
+pub const LOCK_EX: c_int = 2;
+pub const LOCK_NB: c_int = 4;
+
+// modem lines
+pub const TIOCM_DTR: c_int = 0x002;
+pub const TIOCM_RTS: c_int = 0x004;
+pub const TIOCM_CTS: c_int = 0x020;
+pub const TIOCM_CAR: c_int = 0x040;
+pub const TIOCM_RNG: c_int = 0x080;
+pub const TIOCM_DSR: c_int = 0x100;
+
+// netlink
+pub const AF_NETLINK: c_int = 16;
+pub const NETLINK_KOBJECT_UEVENT: c_int = 15;
+pub const SOCK_CLOEXEC: c_int = 0o2000000;
+pub const SOCK_DGRAM: c_int = 2;
+
+// RS-485 flags
+pub const SER_RS485_ENABLED: u32 = 1 << 0;
+pub const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+pub const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+pub const SER_RS485_RX_DURING_TX: u32 = 1 << 4;
+
+// poll events
+pub const POLLIN: c_short = 0x001;
+pub const POLLOUT: c_short = 0x004;
+pub const POLLHUP: c_short = 0x010;
+
+// tcflow actions
+pub const TCOOFF: c_int = 0;
+pub const TCOON: c_int = 1;
+pub const TCIOFF: c_int = 2;
+pub const TCION: c_int = 3;
+
+// tcflush queue selectors
+pub const TCIFLUSH: c_int = 0;
+pub const TCOFLUSH: c_int = 1;
+pub const TCIOFLUSH: c_int = 2;
+
+// ioctl requests
+pub const TIOCEXCL: c_ulong = 0x540C;
+pub const TIOCOUTQ: c_ulong = 0x5411;
+pub const TIOCMGET: c_ulong = 0x5415;
+pub const TIOCMBIS: c_ulong = 0x5416;
+pub const TIOCMBIC: c_ulong = 0x5417;
+pub const TIOCMSET: c_ulong = 0x5418;
+pub const FIONREAD: c_ulong = 0x541B;
+pub const TIOCSBRK: c_ulong = 0x5427;
+pub const TIOCCBRK: c_ulong = 0x5428;
+pub const TCGETS2: c_ulong = 0x802C542A;
+pub const TCSETS2: c_ulong = 0x402C542B;
+pub const TIOCGRS485: c_ulong = 0x542E;
+pub const TIOCSRS485: c_ulong = 0x542F;
+pub const TIOCGPTN: c_ulong = 0x80045430;
+pub const TIOCSPTLCK: c_ulong = 0x40045431;
+pub const TIOCMIWAIT: c_ulong = 0x545C;
+pub const TIOCGICOUNT: c_ulong = 0x545D;
+
+extern {
+    pub fn bind(fd: c_int, addr: *const sockaddr_nl, len: u32) -> c_int;
+    pub fn clock_gettime(clock: c_int, tp: *mut timespec) -> c_int;
+    pub fn epoll_create1(flags: c_int) -> c_int;
+    pub fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut epoll_event) -> c_int;
+    pub fn epoll_wait(
+        epfd: c_int,
+        events: *mut epoll_event,
+        maxevents: c_int,
+        timeout: c_int,
+    ) -> c_int;
+    pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    pub fn flock(fd: c_int, operation: c_int) -> c_int;
+    pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    pub fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+    pub fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+    pub fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+    pub fn tcdrain(fd: c_int) -> c_int;
+    pub fn tcflow(fd: c_int, action: c_int) -> c_int;
+    pub fn tcflush(fd: c_int, queue_selector: c_int) -> c_int;
+    pub fn tcsendbreak(fd: c_int, duration: c_int) -> c_int;
+}
+
+/// Converts the return value of a libc call into an `io::Result`
+pub fn cvt(ret: c_int) -> io::Result<c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Whether the error indicates that the driver doesn't implement an ioctl
+pub fn unsupported(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(libc::EINVAL) | Some(libc::ENOTTY) => true,
+        _ => false,
+    }
+}
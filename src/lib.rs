@@ -6,33 +6,74 @@
 #![feature(fs)]
 #![feature(io)]
 #![feature(io_ext)]
+#![feature(libc)]
 #![feature(path)]
 #![feature(plugin)]
 #![feature(std_misc)]
 
 //! A library for serial port communication
 
+extern crate libc;
 extern crate termios;
 #[cfg(test)]
 extern crate quickcheck;
 
-use std::fmt;
+use std::{cmp, thread};
 use std::fs::{File, self};
 use std::io::{Read, Write, self};
-use std::os::unix::io::AsRawFd;
-use std::path::Path;
-
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libc::{c_int, c_ulong};
+
+pub use capture::{CaptureReader, CaptureSink, CaptureWriter, Record, RecordingPort};
+pub use capture::ReplayPort;
+pub use enumerate::{PortInfo, PortType, UsbInfo, available_ports};
+pub use events::Event;
+pub use line::{LineReader, Terminator};
+pub use mock::{ConfigChange, MockPort};
+pub use modem::{LineCounters, ModemMask, ModemStatus};
+pub use monitor::{PortEvent, PortMonitor};
+pub use pcap::{PcapWriter, capture_to_pcap};
+pub use pty::{PtyPair, virtual_pair};
+pub use reconnect::{LinkEvent, ReconnectingPort};
+pub use rs485::Rs485Config;
+pub use settings::PortSettings;
+pub use split::{SerialReader, SerialWriter};
 pub use termios::BaudRate;
 
 use termios::prelude::*;
 
-#[cfg(test)]
-mod socat;
+use events::Decoder;
+use lock::LockFile;
+
+mod baud;
+mod capture;
+mod clock;
+mod enumerate;
+mod events;
+#[cfg(feature = "evented")]
+pub mod evented;
+mod ffi;
+mod line;
+mod lock;
+mod mock;
+mod modem;
+mod monitor;
+mod pcap;
+mod poll;
+mod pty;
+mod reconnect;
+mod rs485;
+mod settings;
+mod split;
 #[cfg(test)]
 mod test;
 
 /// For how long to block `read()` calls
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct BlockingMode {
     /// The device will block until *at least* `bytes` are received
     pub bytes: u8,
@@ -41,21 +82,34 @@ pub struct BlockingMode {
 }
 
 /// Options and flags which can be used to configure how a serial port is opened.
-pub struct OpenOptions(fs::OpenOptions);
+#[derive(Clone)]
+pub struct OpenOptions {
+    exclusive: bool,
+    lock_dir: Option<PathBuf>,
+    nonblocking: bool,
+    options: fs::OpenOptions,
+    settings: Option<PortSettings>,
+}
 
 impl OpenOptions {
     /// Creates a blank net set of options ready for configuration.
     ///
     /// All options are initially set to false.
     pub fn new() -> OpenOptions {
-        OpenOptions(fs::OpenOptions::new())
+        OpenOptions {
+            exclusive: false,
+            lock_dir: None,
+            nonblocking: false,
+            options: fs::OpenOptions::new(),
+            settings: None,
+        }
     }
 
     /// Set the option for read access.
     ///
     /// This option, when true, will indicate that the serial port should be read-able when opened.
     pub fn read(&mut self, read: bool) -> &mut OpenOptions {
-        self.0.read(read);
+        self.options.read(read);
         self
     }
 
@@ -64,7 +118,45 @@ impl OpenOptions {
     /// This option, when true, will indicate that the serial port should be write-able when
     /// opened.
     pub fn write(&mut self, write: bool) -> &mut OpenOptions {
-        self.0.write(write);
+        self.options.write(write);
+        self
+    }
+
+    /// Set the option for exclusive access.
+    ///
+    /// This option, when true, will make further attempts to open the serial port fail until it's
+    /// closed. This is enforced with both the `TIOCEXCL` ioctl and an advisory `flock`.
+    pub fn exclusive(&mut self, exclusive: bool) -> &mut OpenOptions {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Set the directory where UUCP-style lock files are stored, e.g. `/var/lock`.
+    ///
+    /// When set, opening the serial port will create a `LCK..<name>` lock file in this directory,
+    /// which gets removed when the serial port is closed. Opening will fail if another live
+    /// process holds the lock. Programs like minicom honor these lock files.
+    pub fn lock_dir<P: ?Sized>(&mut self, dir: &P) -> &mut OpenOptions where
+        P: AsRef<Path>,
+    {
+        self.lock_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the option for non-blocking mode.
+    ///
+    /// This option, when true, will make `read()` and `write()` calls that can't make progress
+    /// fail immediately with an error of kind `WouldBlock`. See `SerialPort::set_nonblocking()`.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut OpenOptions {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Set the configuration the serial port will be opened with.
+    ///
+    /// The settings are applied together with the "raw" mode, in a single update of the device.
+    pub fn settings(&mut self, settings: PortSettings) -> &mut OpenOptions {
+        self.settings = Some(settings);
         self
     }
 
@@ -77,13 +169,61 @@ impl OpenOptions {
         self.open_(port.as_ref())
     }
 
+    /// Opens the serial port of a specific USB adapter, regardless of the enumeration order.
+    ///
+    /// The adapter is identified by its vendor and product IDs and, optionally, its serial
+    /// number. If several ports match, e.g. in multiport adapters, the one with the lowest device
+    /// path is opened.
+    pub fn open_by_usb(
+        &self,
+        vid: u16,
+        pid: u16,
+        serial_number: Option<&str>,
+    ) -> io::Result<SerialPort> {
+        for port in try!(available_ports()) {
+            if port.usb.as_ref().map_or(false, |usb| usb.matches(vid, pid, serial_number)) {
+                return self.open(&port.path)
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, "no USB serial port matches"))
+    }
+
     fn open_(&self, path: &Path) -> io::Result<SerialPort> {
-        let file = try!(self.0.open(path));
+        let lock = match self.lock_dir {
+            None => None,
+            Some(ref dir) => Some(try!(LockFile::create(dir, path))),
+        };
+
+        let file = try!(self.options.open(path));
+
+        if self.exclusive {
+            unsafe {
+                try!(ffi::cvt(ffi::ioctl(file.as_raw_fd(), ffi::TIOCEXCL)));
+                try!(ffi::cvt(ffi::flock(file.as_raw_fd(), ffi::LOCK_EX | ffi::LOCK_NB)));
+            }
+        }
+
+        if self.nonblocking {
+            try!(poll::set_nonblocking(file.as_raw_fd(), true));
+        }
 
         let mut termios = try!(Termios::fetch(file.as_raw_fd()));
         termios.make_raw();
 
-        let sp = SerialPort(file);
+        if let Some(ref s) = self.settings {
+            settings::update(&mut termios, s);
+        }
+
+        let sp = SerialPort {
+            decoder: Decoder::new(),
+            drain_on_flush: false,
+            file: file,
+            lock: lock,
+            rs485: None,
+            timeout: None,
+            unread: Vec::new(),
+        };
 
         try!(sp.update(termios));
 
@@ -92,7 +232,19 @@ impl OpenOptions {
 }
 
 /// A serial device
-pub struct SerialPort(File);
+pub struct SerialPort {
+    decoder: Decoder,
+    drain_on_flush: bool,
+    file: File,
+    // Only held to be released when the port is closed
+    #[allow(dead_code)]
+    lock: Option<LockFile>,
+    // Emulated RS-485 mode, for drivers that don't support it
+    rs485: Option<Rs485Config>,
+    timeout: Option<Duration>,
+    // Received data already taken from the device, returned before the data still queued
+    unread: Vec<u8>,
+}
 
 impl SerialPort {
     /// Opens a serial port in "raw" mode with read-only permission
@@ -100,8 +252,34 @@ impl SerialPort {
         OpenOptions::new().open(port)
     }
 
+    /// Changes all the settings of the device at once
+    pub fn apply_settings(&mut self, settings: &PortSettings) -> io::Result<()> {
+        self.fetch().and_then(|mut termios| {
+            settings::update(&mut termios, settings);
+
+            self.update(termios)
+        })
+    }
+
     /// Returns the input and output baud rates
+    ///
+    /// Fails with an error of kind `InvalidInput` if a baud rate has no `BaudRate` variant, e.g.
+    /// after `set_baud_rate_custom()`, use `baud_rate_hz()` to read such baud rates.
     pub fn baud_rate(&self) -> io::Result<(BaudRate, BaudRate)> {
+        match self.fetch2() {
+            Err(ref e) if ffi::unsupported(e) => {},
+            Err(e) => return Err(e),
+            Ok(termios) => {
+                return match (baud::standard(termios.c_ispeed), baud::standard(termios.c_ospeed)) {
+                    (Some(input), Some(output)) => Ok((input, output)),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "non-standard baud rate, see `baud_rate_hz()`",
+                    )),
+                }
+            },
+        }
+
         self.fetch().map(|termios| {
             (termios.ispeed(), termios.ospeed())
         })
@@ -109,64 +287,142 @@ impl SerialPort {
 
     /// Returns the blocking mode used by the device
     pub fn blocking_mode(&self) -> io::Result<BlockingMode> {
-        self.fetch().map(|termios| {
-            BlockingMode {
-                bytes: termios.cc[control::Char::VMIN],
-                deciseconds: termios.cc[control::Char::VTIME],
-            }
-        })
+        self.fetch().map(|termios| settings::blocking_mode(&termios))
+    }
+
+    /// Returns the number of bytes received by the device but not read yet
+    ///
+    /// A `read()` of up to this many bytes won't block.
+    pub fn bytes_to_read(&self) -> io::Result<usize> {
+        self.queue_len(ffi::FIONREAD).map(|len| len + self.unread.len())
+    }
+
+    /// Returns the number of bytes written but not transmitted by the device yet
+    pub fn bytes_to_write(&self) -> io::Result<usize> {
+        self.queue_len(ffi::TIOCOUTQ)
     }
 
     /// Returns the number of data bits used per character
     pub fn data_bits(&self) -> io::Result<DataBits> {
-        self.fetch().map(|termios| {
-            match termios.get::<control::CSIZE>() {
-                control::CSIZE::CS5 => DataBits::Five,
-                control::CSIZE::CS6 => DataBits::Six,
-                control::CSIZE::CS7 => DataBits::Seven,
-                control::CSIZE::CS8 => DataBits::Eight,
-            }
-        })
+        self.fetch().map(|termios| settings::data_bits(&termios))
+    }
+
+    /// Discards the data received but not read yet and/or written but not transmitted yet
+    ///
+    /// This is useful to get rid of stale data after reconfiguring the device.
+    pub fn discard(&mut self, direction: Direction) -> io::Result<()> {
+        let queue = match direction {
+            Direction::Both => ffi::TCIOFLUSH,
+            Direction::Input => ffi::TCIFLUSH,
+            Direction::Output => ffi::TCOFLUSH,
+        };
+
+        try!(ffi::cvt(unsafe { ffi::tcflush(self.file.as_raw_fd(), queue) }));
+
+        if direction != Direction::Output {
+            self.unread.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until all the written data has been physically transmitted
+    pub fn drain(&mut self) -> io::Result<()> {
+        try!(ffi::cvt(unsafe { ffi::tcdrain(self.file.as_raw_fd()) }));
+        Ok(())
+    }
+
+    /// Returns whether `flush()` calls drain the device, see `set_drain_on_flush()`
+    pub fn drain_on_flush(&self) -> bool {
+        self.drain_on_flush
     }
 
     /// Returns the flow control used by the device
     pub fn flow_control(&self) -> io::Result<FlowControl> {
-        self.fetch().map(|termios| {
-            if termios.contains(control::Flag::CRTSCTS) {
-                FlowControl::Hardware
-            } else if termios.contains(input::Flag::IXANY) &&
-                termios.contains(input::Flag::IXOFF) &&
-                termios.contains(input::Flag::IXON)
-            {
-                FlowControl::Software
-            } else {
-                FlowControl::None
-            }
-        })
+        self.fetch().map(|termios| settings::flow_control(&termios))
+    }
+
+    /// Gives up ownership of the file descriptor
+    ///
+    /// The lock file, if any, is removed.
+    pub fn into_raw_fd(self) -> RawFd {
+        let SerialPort { file, .. } = self;
+        let fd = file.as_raw_fd();
+
+        // Keeps the file descriptor open
+        mem::forget(file);
+        fd
     }
 
     /// Returns the bit parity used by the device
     pub fn parity(&self) -> io::Result<Parity> {
-        self.fetch().map(|termios| {
-            match (
-                termios.contains(control::Flag::PARENB),
-                termios.contains(control::Flag::PARODD),
-            ) {
-                (true, true) => Parity::Odd,
-                (true, false) => Parity::Even,
-                (false, _) => Parity::None,
-            }
-        })
+        self.fetch().map(|termios| settings::parity(&termios))
+    }
+
+    /// Blocks until data can be read from the device without blocking
+    ///
+    /// Returns `false` if the `timeout` elapsed first. With no `timeout`, this method may block
+    /// forever. This method is meant to be used in non-blocking mode, to wait until `read()` won't
+    /// fail with an error of kind `WouldBlock`.
+    pub fn poll_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        if !self.unread.is_empty() {
+            return Ok(true)
+        }
+
+        poll::wait(self.file.as_raw_fd(), ffi::POLLIN, timeout)
+    }
+
+    /// Blocks until data can be written to the device without blocking
+    ///
+    /// Returns `false` if the `timeout` elapsed first. With no `timeout`, this method may block
+    /// forever. This method is meant to be used in non-blocking mode, to wait until `write()`
+    /// won't fail with an error of kind `WouldBlock`.
+    pub fn poll_writable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        poll::wait(self.file.as_raw_fd(), ffi::POLLOUT, timeout)
+    }
+
+    /// Transmits a break condition for the given `duration`
+    ///
+    /// If the driver doesn't support breaks of arbitrary duration, a break of 0.25 to 0.5 seconds
+    /// is transmitted instead.
+    pub fn send_break(&mut self, duration: Duration) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+
+        unsafe {
+            match ffi::cvt(ffi::ioctl(fd, ffi::TIOCSBRK)) {
+                Err(ref e) if ffi::unsupported(e) => {
+                    try!(ffi::cvt(ffi::tcsendbreak(fd, 0)));
+                    return Ok(())
+                },
+                result => try!(result),
+            };
+
+            thread::sleep_ms(cmp::max(duration.num_milliseconds(), 0) as u32);
+
+            try!(ffi::cvt(ffi::ioctl(fd, ffi::TIOCCBRK)));
+        }
+
+        Ok(())
+    }
+
+    /// Transmits the XOFF character, asking the other end to stop sending
+    ///
+    /// The XOFF character is configured with `set_flow_control()`.
+    pub fn send_xoff(&mut self) -> io::Result<()> {
+        self.flow(ffi::TCIOFF)
+    }
+
+    /// Transmits the XON character, asking the other end to resume sending
+    ///
+    /// The XON character is configured with `set_flow_control()`.
+    pub fn send_xon(&mut self) -> io::Result<()> {
+        self.flow(ffi::TCION)
     }
 
     /// Changes the baud rate of the input/output or both directions
     pub fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()> {
         self.fetch().and_then(|mut termios| {
-            match direction {
-                Direction::Both => termios.set_speed(rate),
-                Direction::Input => termios.set_ispeed(rate),
-                Direction::Output => termios.set_ospeed(rate),
-            }
+            settings::set_baud_rate(&mut termios, direction, rate);
 
             self.update(termios)
         })
@@ -175,8 +431,7 @@ impl SerialPort {
     /// Changes the blocking mode used by the device
     pub fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()> {
         self.fetch().and_then(|mut termios| {
-            termios.cc[control::Char::VMIN] = mode.bytes;
-            termios.cc[control::Char::VTIME] = mode.deciseconds;
+            settings::set_blocking_mode(&mut termios, mode);
 
             self.update(termios)
         })
@@ -185,59 +440,42 @@ impl SerialPort {
     /// Changes the number of data bits per character
     pub fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()> {
         self.fetch().and_then(|mut termios| {
-            termios.set(match bits {
-                DataBits::Five => control::CSIZE::CS5,
-                DataBits::Six => control::CSIZE::CS6,
-                DataBits::Seven => control::CSIZE::CS7,
-                DataBits::Eight => control::CSIZE::CS8,
-            });
+            settings::set_data_bits(&mut termios, bits);
 
             self.update(termios)
         })
     }
 
+    /// Makes `flush()` calls block until the written data has been physically transmitted
+    ///
+    /// By default `flush()` returns right away, as the kernel has no user space buffer to flush.
+    pub fn set_drain_on_flush(&mut self, drain: bool) {
+        self.drain_on_flush = drain;
+    }
+
     /// Changes the flow control used by the device
     pub fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()> {
         self.fetch().and_then(|mut termios| {
-            match flow {
-                FlowControl::Hardware => {
-                    termios.clear(input::Flag::IXANY);
-                    termios.clear(input::Flag::IXOFF);
-                    termios.clear(input::Flag::IXON);
-                    termios.set(control::Flag::CRTSCTS);
-                },
-                FlowControl::None => {
-                    termios.clear(control::Flag::CRTSCTS);
-                    termios.clear(input::Flag::IXANY);
-                    termios.clear(input::Flag::IXOFF);
-                    termios.clear(input::Flag::IXON);
-                },
-                FlowControl::Software => {
-                    termios.clear(control::Flag::CRTSCTS);
-                    termios.set(input::Flag::IXANY);
-                    termios.set(input::Flag::IXOFF);
-                    termios.set(input::Flag::IXON);
-                },
-            }
+            settings::set_flow_control(&mut termios, flow);
 
             self.update(termios)
         })
     }
 
+    /// Enables or disables the non-blocking mode
+    ///
+    /// In non-blocking mode, `read()` and `write()` calls that can't make progress fail
+    /// immediately with an error of kind `WouldBlock`, regardless of the blocking mode and the
+    /// timeout. Use `poll_readable()`/`poll_writable()` or register the file descriptor with an
+    /// event loop to find out when the device is ready.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        poll::set_nonblocking(self.file.as_raw_fd(), nonblocking)
+    }
+
     /// Changes the bit parity used by the device
     pub fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
         self.fetch().and_then(|mut termios| {
-            match parity {
-                Parity::Even => {
-                    termios.clear(control::Flag::PARODD);
-                    termios.set(control::Flag::PARENB);
-                },
-                Parity::None => termios.clear(control::Flag::PARENB),
-                Parity::Odd => {
-                    termios.set(control::Flag::PARENB);
-                    termios.set(control::Flag::PARODD);
-                },
-            }
+            settings::set_parity(&mut termios, parity);
 
             self.update(termios)
         })
@@ -246,66 +484,304 @@ impl SerialPort {
     /// Changes the number of stop bits per character
     pub fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()> {
         self.fetch().and_then(|mut termios| {
-            match bits {
-                StopBits::One => termios.clear(control::Flag::CSTOPB),
-                StopBits::Two => termios.set(control::Flag::CSTOPB),
-            }
+            settings::set_stop_bits(&mut termios, bits);
 
             self.update(termios)
         })
     }
 
+    /// Changes how long `read()` and `write()` calls may block
+    ///
+    /// When a `timeout` is set, `read()` and `write()` calls that can't make progress within
+    /// `timeout` fail with an error of kind `TimedOut`. `read()` calls return as soon as some data
+    /// is available, hence a blocking mode that waits for more than one byte (see
+    /// `set_blocking_mode()`) may still block them past the `timeout`. With no `timeout`, the
+    /// calls may block forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns all the settings of the device
+    pub fn settings(&self) -> io::Result<PortSettings> {
+        let baud_rate = try!(self.baud_rate());
+
+        self.fetch().map(|termios| settings::fetch(&termios, baud_rate))
+    }
+
     /// Returns the number of stop bits per character
     pub fn stop_bits(&self) -> io::Result<StopBits> {
-        self.fetch().map(|termios| {
-            if termios.contains(control::Flag::CSTOPB) {
-                StopBits::Two
-            } else {
-                StopBits::One
-            }
-        })
+        self.fetch().map(|termios| settings::stop_bits(&termios))
+    }
+
+    /// Returns how long `read()` and `write()` calls may block
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Writes a single byte with the ninth bit set
+    ///
+    /// This emulates the 9-bit addressing used on multidrop buses with mark parity: once all the
+    /// pending output has been transmitted, the port switches to mark parity, writes `address`,
+    /// and switches back to its previous parity after `address` has been transmitted. Data bytes
+    /// should be sent with space parity, so their ninth bit is clear.
+    pub fn write_address(&mut self, address: u8) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        let original = try!(self.fetch());
+        let mut marked = try!(self.fetch());
+
+        settings::set_parity(&mut marked, Parity::Mark);
+        try!(marked.update(fd, When::Drain));
+
+        let written = self.write_all(&[address]);
+        let restored = original.update(fd, When::Drain);
+
+        written.and(restored)
     }
 
     /// Fetches the current state of the termios structure
     fn fetch(&self) -> io::Result<Termios> {
-        Termios::fetch(self.0.as_raw_fd())
+        Termios::fetch(self.file.as_raw_fd())
     }
 
     /// Updates the underlying termios structure
     fn update(&self, termios: Termios) -> io::Result<()> {
-        termios.update(self.0.as_raw_fd(), When::Now)
+        termios.update(self.file.as_raw_fd(), When::Now)
+    }
+
+    /// Performs the `tcflow` `action`
+    fn flow(&self, action: c_int) -> io::Result<()> {
+        try!(ffi::cvt(unsafe { ffi::tcflow(self.file.as_raw_fd(), action) }));
+        Ok(())
+    }
+
+    /// Fetches the number of bytes in the queue selected by the `FIONREAD`/`TIOCOUTQ` `request`
+    fn queue_len(&self, request: c_ulong) -> io::Result<usize> {
+        let mut len: c_int = 0;
+        let ptr: *mut c_int = &mut len;
+
+        unsafe {
+            try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), request, ptr)));
+        }
+        Ok(len as usize)
+    }
+
+    /// Fetches the current state of the termios2 structure
+    fn fetch2(&self) -> io::Result<ffi::termios2> {
+        unsafe {
+            let mut termios: ffi::termios2 = mem::zeroed();
+            let ptr: *mut ffi::termios2 = &mut termios;
+            try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), ffi::TCGETS2, ptr)));
+            Ok(termios)
+        }
+    }
+
+    /// Updates the underlying termios2 structure
+    fn update2(&self, termios: &ffi::termios2) -> io::Result<()> {
+        unsafe {
+            let ptr: *const ffi::termios2 = termios;
+            try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), ffi::TCSETS2, ptr)));
+            Ok(())
+        }
     }
 }
 
-impl Read for SerialPort {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+impl AsRawFd for SerialPort {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
     }
+}
 
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        self.0.read_to_end(buf)
+impl FromRawFd for SerialPort {
+    /// Takes ownership of the file descriptor of an open serial device
+    ///
+    /// The configuration of the device is left untouched.
+    unsafe fn from_raw_fd(fd: RawFd) -> SerialPort {
+        SerialPort {
+            decoder: Decoder::new(),
+            drain_on_flush: false,
+            file: File::from_raw_fd(fd),
+            lock: None,
+            rs485: None,
+            timeout: None,
+            unread: Vec::new(),
+        }
     }
+}
 
-    fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
-        self.0.read_to_string(buf)
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unread.is_empty() {
+            return poll::read(&mut self.file, buf, self.timeout)
+        }
+
+        let n = cmp::min(buf.len(), self.unread.len());
+
+        for (dst, src) in buf.iter_mut().zip(self.unread.iter()) {
+            *dst = *src;
+        }
+
+        self.unread = self.unread[n..].to_vec();
+        Ok(n)
     }
 }
 
 impl Write for SerialPort {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        match self.rs485 {
+            None => poll::write(&mut self.file, buf, self.timeout),
+            Some(config) => rs485::write(self, buf, &config),
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+        if self.drain_on_flush {
+            self.drain()
+        } else {
+            self.file.flush()
+        }
     }
+}
+
+/// Operations common to serial ports, real or not
+///
+/// Code written against this trait can be tested with a `MockPort` instead of a real device.
+pub trait Serial: Read + Write {
+    /// Returns the input and output baud rates
+    fn baud_rate(&self) -> io::Result<(BaudRate, BaudRate)>;
+
+    /// Returns the blocking mode
+    fn blocking_mode(&self) -> io::Result<BlockingMode>;
+
+    /// Returns the number of data bits used per character
+    fn data_bits(&self) -> io::Result<DataBits>;
+
+    /// Returns the flow control
+    fn flow_control(&self) -> io::Result<FlowControl>;
+
+    /// Returns the bit parity
+    fn parity(&self) -> io::Result<Parity>;
+
+    /// Changes the baud rate of the input/output or both directions
+    fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()>;
+
+    /// Changes the blocking mode
+    fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()>;
+
+    /// Changes the number of data bits per character
+    fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()>;
+
+    /// Changes the flow control
+    fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()>;
+
+    /// Changes the bit parity
+    fn set_parity(&mut self, parity: Parity) -> io::Result<()>;
 
-    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        self.0.write_all(buf)
+    /// Changes the number of stop bits per character
+    fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()>;
+
+    /// Changes how long `read()` and `write()` calls may block
+    fn set_timeout(&mut self, timeout: Option<Duration>);
+
+    /// Returns the number of stop bits per character
+    fn stop_bits(&self) -> io::Result<StopBits>;
+
+    /// Returns how long `read()` and `write()` calls may block
+    fn timeout(&self) -> Option<Duration>;
+
+    /// Changes all the settings at once
+    ///
+    /// The default implementation calls each setter in turn.
+    fn apply_settings(&mut self, settings: &PortSettings) -> io::Result<()> {
+        let (input, output) = settings.baud_rate;
+
+        try!(self.set_baud_rate(Direction::Input, input));
+        try!(self.set_baud_rate(Direction::Output, output));
+        try!(self.set_blocking_mode(settings.blocking_mode));
+        try!(self.set_data_bits(settings.data_bits));
+        try!(self.set_flow_control(settings.flow_control));
+        try!(self.set_parity(settings.parity));
+        self.set_stop_bits(settings.stop_bits)
+    }
+
+    /// Returns all the settings
+    ///
+    /// The default implementation calls each getter in turn.
+    fn settings(&self) -> io::Result<PortSettings> {
+        Ok(PortSettings {
+            baud_rate: try!(self.baud_rate()),
+            blocking_mode: try!(self.blocking_mode()),
+            data_bits: try!(self.data_bits()),
+            flow_control: try!(self.flow_control()),
+            parity: try!(self.parity()),
+            stop_bits: try!(self.stop_bits()),
+        })
+    }
+}
+
+impl Serial for SerialPort {
+    fn baud_rate(&self) -> io::Result<(BaudRate, BaudRate)> {
+        SerialPort::baud_rate(self)
+    }
+
+    fn blocking_mode(&self) -> io::Result<BlockingMode> {
+        SerialPort::blocking_mode(self)
+    }
+
+    fn data_bits(&self) -> io::Result<DataBits> {
+        SerialPort::data_bits(self)
+    }
+
+    fn flow_control(&self) -> io::Result<FlowControl> {
+        SerialPort::flow_control(self)
+    }
+
+    fn parity(&self) -> io::Result<Parity> {
+        SerialPort::parity(self)
+    }
+
+    fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()> {
+        SerialPort::set_baud_rate(self, direction, rate)
+    }
+
+    fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()> {
+        SerialPort::set_blocking_mode(self, mode)
+    }
+
+    fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()> {
+        SerialPort::set_data_bits(self, bits)
+    }
+
+    fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()> {
+        SerialPort::set_flow_control(self, flow)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
+        SerialPort::set_parity(self, parity)
+    }
+
+    fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()> {
+        SerialPort::set_stop_bits(self, bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        SerialPort::set_timeout(self, timeout)
+    }
+
+    fn stop_bits(&self) -> io::Result<StopBits> {
+        SerialPort::stop_bits(self)
     }
 
-    fn write_fmt(&mut self, fmt: fmt::Arguments) -> io::Result<()> {
-        self.0.write_fmt(fmt)
+    fn timeout(&self) -> Option<Duration> {
+        SerialPort::timeout(self)
+    }
+
+    /// Updates the device once, see `SerialPort::apply_settings()`
+    fn apply_settings(&mut self, settings: &PortSettings) -> io::Result<()> {
+        SerialPort::apply_settings(self, settings)
+    }
+
+    fn settings(&self) -> io::Result<PortSettings> {
+        SerialPort::settings(self)
     }
 }
 
@@ -320,7 +796,7 @@ pub enum DataBits {
 }
 
 #[allow(missing_docs)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Direction {
     Both,
     Input,
@@ -331,9 +807,13 @@ pub enum Direction {
 /// Flow control
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FlowControl {
+    /// RTS/CTS and XON/XOFF handshakes at once
+    Both(SoftwareFlowControl),
+    /// RTS/CTS handshake
     Hardware,
     None,
-    Software,
+    /// XON/XOFF handshake
+    Software(SoftwareFlowControl),
 }
 
 #[allow(missing_docs)]
@@ -341,8 +821,45 @@ pub enum FlowControl {
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Parity {
     Even,
+    /// The parity bit is always 1
+    Mark,
     None,
     Odd,
+    /// The parity bit is always 0
+    Space,
+}
+
+/// XON/XOFF flow control configuration
+///
+/// Each direction can be enabled independently. Make sure that the XON and XOFF characters
+/// don't appear in the transmitted data, e.g. by only sending text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoftwareFlowControl {
+    /// Whether any received character, not just XON, resumes the output stopped by XOFF
+    ///
+    /// Only meaningful when `output` is enabled.
+    pub any: bool,
+    /// Whether the device sends XOFF when its input buffer fills up, and XON when it drains
+    pub input: bool,
+    /// Whether the output is stopped when XOFF is received, and resumed when XON is received
+    pub output: bool,
+    /// The character that stops the output, `\x13` (DC3) by default
+    pub xoff: u8,
+    /// The character that resumes the output, `\x11` (DC1) by default
+    pub xon: u8,
+}
+
+impl SoftwareFlowControl {
+    /// Both directions enabled, only XON resumes the output, with the usual XON/XOFF characters
+    pub fn new() -> SoftwareFlowControl {
+        SoftwareFlowControl {
+            any: false,
+            input: true,
+            output: true,
+            xoff: 0x13,
+            xon: 0x11,
+        }
+    }
 }
 
 #[allow(missing_docs)]
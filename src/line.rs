@@ -0,0 +1,216 @@
+//! Line-oriented reading
+
+use std::cmp;
+use std::io::{ErrorKind, Read, self};
+use std::time::Duration;
+
+use clock;
+use {BlockingMode, SerialPort};
+
+/// Default value of `LineReader::max_len()`
+const MAX_LEN: usize = 4096;
+
+/// The sequence of bytes that ends a line
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    /// Carriage return, `\r`
+    Cr,
+    /// Carriage return followed by a line feed, `\r\n`
+    CrLf,
+    /// A custom delimiter, which must not be empty
+    Custom(Vec<u8>),
+    /// Line feed, `\n`
+    Lf,
+}
+
+impl Terminator {
+    fn as_bytes(&self) -> &[u8] {
+        match *self {
+            Terminator::Cr => b"\r",
+            Terminator::CrLf => b"\r\n",
+            Terminator::Custom(ref delimiter) => delimiter,
+            Terminator::Lf => b"\n",
+        }
+    }
+}
+
+/// Reads newline-terminated (or otherwise delimited) lines from a serial port
+///
+/// Unlike `BufRead::read_line()`, which may block forever waiting for the end of a line, each
+/// `read_line()` call gives up once the line timeout elapses or the line grows past the maximum
+/// length, and hands out the data received so far.
+///
+/// While wrapped, the port is switched to a blocking mode that returns the available data right
+/// away (`BlockingMode { bytes: 0, deciseconds: 0 }`), otherwise a `read()` waiting for more
+/// bytes than available would block past the line timeout. Don't change the blocking mode
+/// through `get_mut()`.
+pub struct LineReader {
+    blocking_mode: BlockingMode,
+    buf: Vec<u8>,
+    max_len: usize,
+    port: SerialPort,
+    terminator: Terminator,
+    timeout: Option<Duration>,
+}
+
+impl LineReader {
+    /// Reads lines ending with `terminator` from `port`
+    ///
+    /// Lines have no timeout and a maximum length of 4096 bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `terminator` is an empty custom delimiter.
+    pub fn new(mut port: SerialPort, terminator: Terminator) -> io::Result<LineReader> {
+        assert!(!terminator.as_bytes().is_empty(), "empty line terminator");
+
+        let blocking_mode = try!(port.blocking_mode());
+        try!(port.set_blocking_mode(BlockingMode { bytes: 0, deciseconds: 0 }));
+
+        Ok(LineReader {
+            blocking_mode: blocking_mode,
+            buf: Vec::new(),
+            max_len: MAX_LEN,
+            port: port,
+            terminator: terminator,
+            timeout: None,
+        })
+    }
+
+    /// Returns the data received past the last line handed out
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns a reference to the underlying port
+    pub fn get_ref(&self) -> &SerialPort {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port
+    ///
+    /// Reading from the port directly skips the data already buffered by the reader.
+    pub fn get_mut(&mut self) -> &mut SerialPort {
+        &mut self.port
+    }
+
+    /// Restores the blocking mode of the port and returns it, the buffered data is lost
+    pub fn into_inner(mut self) -> io::Result<SerialPort> {
+        try!(self.port.set_blocking_mode(self.blocking_mode));
+        Ok(self.port)
+    }
+
+    /// Returns the maximum length of a line, terminator included
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Reads a line and appends it, terminator included, to `line`
+    ///
+    /// Returns the number of bytes appended, `0` means the device reached the end of file (e.g.
+    /// it hung up) with no data pending.
+    ///
+    /// If the whole line doesn't arrive within the timeout (see `set_timeout()`), the data
+    /// received so far is appended to `line` and the call fails with an error of kind
+    /// `TimedOut`. If the line is longer than `max_len()`, its first `max_len()` bytes are
+    /// appended to `line` and the call fails with an error of kind `InvalidInput`, the rest of
+    /// the line is returned by the next call.
+    pub fn read_line(&mut self, line: &mut Vec<u8>) -> io::Result<usize> {
+        let deadline = self.timeout.map(|timeout| clock::monotonic() + timeout);
+        let mut chunk = [0; 256];
+
+        loop {
+            let limit = cmp::min(self.buf.len(), self.max_len);
+
+            if let Some(end) = find(&self.buf[..limit], self.terminator.as_bytes()) {
+                return Ok(self.consume(end, line))
+            } else if limit == self.max_len {
+                self.consume(limit, line);
+
+                return Err(io::Error::new(ErrorKind::InvalidInput, "line too long"))
+            }
+
+            let remaining = deadline.map(|deadline| deadline - clock::monotonic());
+
+            match remaining {
+                Some(remaining) if remaining <= Duration::zero() => {
+                    let n = self.buf.len();
+                    self.consume(n, line);
+
+                    return Err(io::Error::new(ErrorKind::TimedOut, "line timed out"))
+                },
+                _ => {},
+            }
+
+            if !try!(self.port.poll_readable(remaining)) {
+                continue
+            }
+
+            match self.port.read(&mut chunk) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e),
+                Ok(0) => {
+                    let n = self.buf.len();
+
+                    return Ok(self.consume(n, line))
+                },
+                Ok(n) => self.buf.extend(chunk[..n].iter().cloned()),
+            }
+        }
+    }
+
+    /// Changes the maximum length of a line, terminator included
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_len` is zero.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        assert!(max_len > 0, "zero maximum line length");
+
+        self.max_len = max_len;
+    }
+
+    /// Changes the terminator of the following lines
+    ///
+    /// # Panics
+    ///
+    /// Panics if `terminator` is an empty custom delimiter.
+    pub fn set_terminator(&mut self, terminator: Terminator) {
+        assert!(!terminator.as_bytes().is_empty(), "empty line terminator");
+
+        self.terminator = terminator;
+    }
+
+    /// Changes for how long a `read_line()` call waits for a whole line
+    ///
+    /// The timeout starts when `read_line()` is called. With no `timeout`, `read_line()` blocks
+    /// until the line is complete, it's too long or the device reaches the end of file.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the line terminator
+    pub fn terminator(&self) -> &Terminator {
+        &self.terminator
+    }
+
+    /// Returns for how long a `read_line()` call waits for a whole line
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Moves the first `n` buffered bytes to `line`
+    fn consume(&mut self, n: usize, line: &mut Vec<u8>) -> usize {
+        line.extend(self.buf[..n].iter().cloned());
+        self.buf = self.buf[n..].to_vec();
+        n
+    }
+}
+
+/// Returns the end of the first occurrence of `terminator` in `buf`
+fn find(buf: &[u8], terminator: &[u8]) -> Option<usize> {
+    buf.windows(terminator.len())
+        .position(|window| window == terminator)
+        .map(|start| start + terminator.len())
+}
@@ -0,0 +1,81 @@
+use std::fs::{File, self};
+use std::io::{Read, Write, self};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+use libc;
+
+/// Tells apart the temporary files created by the threads of this process
+static TMP_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A UUCP-style lock file, e.g. `/var/lock/LCK..ttyUSB0`
+///
+/// The lock file contains the PID of its owner and gets removed when dropped.
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Locks `port` by creating a lock file in the `dir` directory
+    ///
+    /// Lock files left behind by dead processes are replaced.
+    pub fn create(dir: &Path, port: &Path) -> io::Result<LockFile> {
+        let name = match port.file_name().and_then(|name| name.to_str()) {
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid port path")),
+            Some(name) => name,
+        };
+        let pid = unsafe { libc::getpid() };
+
+        let lock = dir.join(&format!("LCK..{}", name));
+        let counter = TMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let tmp = dir.join(&format!("LTMP.{}.{}.{}", pid, name, counter));
+
+        // The lock is created atomically by hard linking a fully written temporary file
+        try!(File::create(&tmp).and_then(|mut file| write!(file, "{:>10}\n", pid)));
+        let result = link(&tmp, &lock);
+        let _ = fs::remove_file(&tmp);
+
+        result.map(|_| LockFile { path: lock })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn link(tmp: &Path, lock: &Path) -> io::Result<()> {
+    match fs::hard_link(tmp, lock) {
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+        result => return result,
+    }
+
+    if !is_stale(lock) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the serial port is locked by another process",
+        ));
+    }
+
+    try!(fs::remove_file(lock));
+    fs::hard_link(tmp, lock)
+}
+
+/// Whether the owner of the lock file is dead
+fn is_stale(lock: &Path) -> bool {
+    let mut contents = String::new();
+
+    if File::open(lock).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+        return false;
+    }
+
+    match contents.trim().parse::<libc::pid_t>() {
+        // A corrupted lock file can't be owned by anyone
+        Err(_) => true,
+        Ok(pid) => unsafe {
+            libc::kill(pid, 0) == -1 && io::Error::last_os_error().raw_os_error() ==
+                Some(libc::ESRCH)
+        },
+    }
+}
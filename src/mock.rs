@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write, self};
+use std::time::Duration;
+use std::{cmp, thread};
+
+use {BaudRate, BlockingMode, DataBits, Direction, FlowControl, Parity, PortSettings, Serial};
+use StopBits;
+
+/// A configuration change made to a `MockPort`
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigChange {
+    BaudRate(Direction, BaudRate),
+    BlockingMode(BlockingMode),
+    DataBits(DataBits),
+    FlowControl(FlowControl),
+    Parity(Parity),
+    StopBits(StopBits),
+    Timeout(Option<Duration>),
+}
+
+/// A step of the script played by a `MockPort`
+enum Step {
+    Delay(Duration),
+    Error(io::Error),
+    Read(Vec<u8>),
+    Write(Vec<u8>),
+}
+
+/// An in-memory serial port that plays a script
+///
+/// The script is a queue of expected writes, data to be read, errors and delays, which are
+/// consumed in order by the `read()`/`write()` calls:
+///
+/// - A `read()` returns the data queued with `push_read()`, and fails with an error of kind
+///   `TimedOut` if the next step is an expected write or the script is over.
+/// - A `write()` must match the data queued with `expect_write()`, otherwise it panics.
+/// - An error queued with `push_error()` is returned by the next `read()`/`write()`.
+/// - A delay queued with `push_delay()` makes the next `read()`/`write()` sleep.
+///
+/// Configuration changes are applied to the settings reported by the getters, and recorded so
+/// tests can check them with `changes()`.
+pub struct MockPort {
+    changes: Vec<ConfigChange>,
+    script: VecDeque<Step>,
+    settings: PortSettings,
+    timeout: Option<Duration>,
+}
+
+impl MockPort {
+    /// Creates a port with the default settings (see `PortSettings::new()`) and an empty script
+    pub fn new() -> MockPort {
+        MockPort {
+            changes: Vec::new(),
+            script: VecDeque::new(),
+            settings: PortSettings::new(),
+            timeout: None,
+        }
+    }
+
+    /// Returns the configuration changes made so far, oldest first
+    pub fn changes(&self) -> &[ConfigChange] {
+        &self.changes
+    }
+
+    /// Queues data that must be written to the port
+    ///
+    /// The data may be written by several `write()` calls.
+    pub fn expect_write(&mut self, data: &[u8]) -> &mut MockPort {
+        self.script.push_back(Step::Write(data.to_vec()));
+        self
+    }
+
+    /// Returns whether the whole script has been played
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    /// Queues a delay
+    pub fn push_delay(&mut self, delay: Duration) -> &mut MockPort {
+        self.script.push_back(Step::Delay(delay));
+        self
+    }
+
+    /// Queues an error
+    pub fn push_error(&mut self, error: io::Error) -> &mut MockPort {
+        self.script.push_back(Step::Error(error));
+        self
+    }
+
+    /// Queues data to be read from the port
+    ///
+    /// The data may be read by several `read()` calls.
+    pub fn push_read(&mut self, data: &[u8]) -> &mut MockPort {
+        self.script.push_back(Step::Read(data.to_vec()));
+        self
+    }
+
+    /// Panics if some of the script hasn't been played
+    pub fn verify(&self) {
+        match self.script.front() {
+            None => {},
+            Some(&Step::Delay(delay)) => panic!("the script has a pending delay of {:?}", delay),
+            Some(&Step::Error(ref e)) => panic!("the script has a pending error: {}", e),
+            Some(&Step::Read(ref data)) => panic!("{:?} was never read", data),
+            Some(&Step::Write(ref data)) => panic!("{:?} was never written", data),
+        }
+    }
+
+    /// Plays the leading delays and errors of the script
+    fn play(&mut self) -> io::Result<()> {
+        loop {
+            match self.script.front() {
+                Some(&Step::Delay(delay)) => {
+                    thread::sleep_ms(cmp::max(delay.num_milliseconds(), 0) as u32);
+                },
+                Some(&Step::Error(_)) => {},
+                _ => return Ok(()),
+            }
+
+            if let Some(Step::Error(e)) = self.script.pop_front() {
+                return Err(e)
+            }
+        }
+    }
+
+    fn record(&mut self, change: ConfigChange) {
+        self.changes.push(change);
+    }
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        try!(self.play());
+
+        let (n, done) = match self.script.front_mut() {
+            Some(&mut Step::Read(ref mut data)) => {
+                let n = cmp::min(buf.len(), data.len());
+
+                for (dst, src) in buf.iter_mut().zip(data.iter()) {
+                    *dst = *src;
+                }
+
+                *data = data[n..].to_vec();
+                (n, data.is_empty())
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::TimedOut, "no scripted data to read")),
+        };
+
+        if done {
+            self.script.pop_front();
+        }
+
+        Ok(n)
+    }
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.play());
+
+        let (n, done) = match self.script.front_mut() {
+            Some(&mut Step::Write(ref mut expected)) => {
+                let n = cmp::min(buf.len(), expected.len());
+
+                if buf[..n] != expected[..n] {
+                    panic!("expected a write of {:?} - got {:?}", &expected[..n], &buf[..n])
+                }
+
+                *expected = expected[n..].to_vec();
+                (n, expected.is_empty())
+            },
+            _ => panic!("unexpected write of {:?}", buf),
+        };
+
+        if done {
+            self.script.pop_front();
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Serial for MockPort {
+    fn baud_rate(&self) -> io::Result<(BaudRate, BaudRate)> {
+        Ok(self.settings.baud_rate)
+    }
+
+    fn blocking_mode(&self) -> io::Result<BlockingMode> {
+        Ok(self.settings.blocking_mode)
+    }
+
+    fn data_bits(&self) -> io::Result<DataBits> {
+        Ok(self.settings.data_bits)
+    }
+
+    fn flow_control(&self) -> io::Result<FlowControl> {
+        Ok(self.settings.flow_control)
+    }
+
+    fn parity(&self) -> io::Result<Parity> {
+        Ok(self.settings.parity)
+    }
+
+    fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> io::Result<()> {
+        self.settings.set_baud_rate(direction, rate);
+        self.record(ConfigChange::BaudRate(direction, rate));
+        Ok(())
+    }
+
+    fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()> {
+        self.settings.set_blocking_mode(mode);
+        self.record(ConfigChange::BlockingMode(mode));
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, bits: DataBits) -> io::Result<()> {
+        self.settings.set_data_bits(bits);
+        self.record(ConfigChange::DataBits(bits));
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow: FlowControl) -> io::Result<()> {
+        self.settings.set_flow_control(flow);
+        self.record(ConfigChange::FlowControl(flow));
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> io::Result<()> {
+        self.settings.set_parity(parity);
+        self.record(ConfigChange::Parity(parity));
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, bits: StopBits) -> io::Result<()> {
+        self.settings.set_stop_bits(bits);
+        self.record(ConfigChange::StopBits(bits));
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.record(ConfigChange::Timeout(timeout));
+    }
+
+    fn stop_bits(&self) -> io::Result<StopBits> {
+        Ok(self.settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
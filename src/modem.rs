@@ -0,0 +1,258 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use libc::c_int;
+
+use ffi;
+use SerialPort;
+
+/// Interrupt counters kept by the serial driver
+///
+/// Each counter is incremented by the driver when the corresponding event happens, and wraps
+/// around on overflow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineCounters {
+    /// Number of received breaks
+    pub brk: u32,
+    /// Number of characters dropped because the tty buffer was full
+    pub buf_overrun: u32,
+    /// Number of Carrier Detect transitions
+    pub cd: u32,
+    /// Number of Clear To Send transitions
+    pub cts: u32,
+    /// Number of Data Set Ready transitions
+    pub dsr: u32,
+    /// Number of framing errors
+    pub frame: u32,
+    /// Number of hardware overruns
+    pub overrun: u32,
+    /// Number of parity errors
+    pub parity: u32,
+    /// Number of Ring Indicator transitions
+    pub ri: u32,
+    /// Number of received characters
+    pub rx: u32,
+    /// Number of transmitted characters
+    pub tx: u32,
+}
+
+/// Selects which modem input lines to watch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModemMask {
+    /// Carrier Detect
+    pub cd: bool,
+    /// Clear To Send
+    pub cts: bool,
+    /// Data Set Ready
+    pub dsr: bool,
+    /// Ring Indicator
+    pub ri: bool,
+}
+
+/// State of the modem control lines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModemStatus {
+    /// Carrier Detect
+    pub cd: bool,
+    /// Clear To Send
+    pub cts: bool,
+    /// Data Set Ready
+    pub dsr: bool,
+    /// Data Terminal Ready, driven by this end
+    pub dtr: bool,
+    /// Ring Indicator
+    pub ri: bool,
+    /// Request To Send, driven by this end
+    pub rts: bool,
+}
+
+/// How often the line counters (or levels) are sampled while waiting for a modem line change
+/// with a timeout
+const POLL_INTERVAL_MS: u32 = 10;
+
+impl SerialPort {
+    /// Returns the interrupt counters of the serial driver
+    pub fn line_counters(&self) -> io::Result<LineCounters> {
+        let mut counters: ffi::serial_icounter_struct = unsafe { mem::zeroed() };
+        let ptr: *mut ffi::serial_icounter_struct = &mut counters;
+
+        unsafe {
+            try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), ffi::TIOCGICOUNT, ptr)));
+        }
+
+        Ok(LineCounters {
+            brk: counters.brk as u32,
+            buf_overrun: counters.buf_overrun as u32,
+            cd: counters.dcd as u32,
+            cts: counters.cts as u32,
+            dsr: counters.dsr as u32,
+            frame: counters.frame as u32,
+            overrun: counters.overrun as u32,
+            parity: counters.parity as u32,
+            ri: counters.rng as u32,
+            rx: counters.rx as u32,
+            tx: counters.tx as u32,
+        })
+    }
+
+    /// Returns the state of all the modem control lines
+    pub fn modem_status(&self) -> io::Result<ModemStatus> {
+        self.modem_lines().map(|lines| {
+            ModemStatus {
+                cd: lines & ffi::TIOCM_CAR != 0,
+                cts: lines & ffi::TIOCM_CTS != 0,
+                dsr: lines & ffi::TIOCM_DSR != 0,
+                dtr: lines & ffi::TIOCM_DTR != 0,
+                ri: lines & ffi::TIOCM_RNG != 0,
+                rts: lines & ffi::TIOCM_RTS != 0,
+            }
+        })
+    }
+
+    /// Reads the state of the Carrier Detect line
+    pub fn read_cd(&self) -> io::Result<bool> {
+        self.modem_lines().map(|lines| lines & ffi::TIOCM_CAR != 0)
+    }
+
+    /// Reads the state of the Clear To Send line
+    pub fn read_cts(&self) -> io::Result<bool> {
+        self.modem_lines().map(|lines| lines & ffi::TIOCM_CTS != 0)
+    }
+
+    /// Reads the state of the Data Set Ready line
+    pub fn read_dsr(&self) -> io::Result<bool> {
+        self.modem_lines().map(|lines| lines & ffi::TIOCM_DSR != 0)
+    }
+
+    /// Reads the state of the Ring Indicator line
+    pub fn read_ri(&self) -> io::Result<bool> {
+        self.modem_lines().map(|lines| lines & ffi::TIOCM_RNG != 0)
+    }
+
+    /// Blocks until any of the modem input lines selected by `mask` changes its state
+    ///
+    /// If `timeout` elapses before that, an error of kind `TimedOut` is returned. Without a
+    /// timeout, this method may block forever. An empty `mask` is rejected with an error of kind
+    /// `InvalidInput`.
+    ///
+    /// With a timeout, the transition counters of the driver (see `line_counters()`) are sampled
+    /// every few milliseconds. If the driver doesn't keep counters, the levels of the lines are
+    /// sampled instead, which misses pulses shorter than the sampling interval.
+    pub fn wait_for_modem_change(
+        &self,
+        mask: ModemMask,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let mut lines = 0;
+
+        if mask.cd { lines |= ffi::TIOCM_CAR }
+        if mask.cts { lines |= ffi::TIOCM_CTS }
+        if mask.dsr { lines |= ffi::TIOCM_DSR }
+        if mask.ri { lines |= ffi::TIOCM_RNG }
+
+        if lines == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no modem line selected"))
+        }
+
+        let timeout = match timeout {
+            None => {
+                unsafe {
+                    try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), ffi::TIOCMIWAIT, lines)));
+                }
+                return Ok(())
+            },
+            Some(timeout) => timeout,
+        };
+
+        // `TIOCMIWAIT` can't be interrupted by a timeout, instead watch the transition counters
+        let counters = match self.line_counters() {
+            Ok(_) => true,
+            Err(ref e) if ffi::unsupported(e) => false,
+            Err(e) => return Err(e),
+        };
+        let sample = || -> io::Result<(u32, u32, u32, u32)> {
+            if !counters {
+                return self.modem_lines().map(|levels| ((levels & lines) as u32, 0, 0, 0))
+            }
+
+            self.line_counters().map(|counters| {
+                (
+                    if mask.cd { counters.cd } else { 0 },
+                    if mask.cts { counters.cts } else { 0 },
+                    if mask.dsr { counters.dsr } else { 0 },
+                    if mask.ri { counters.ri } else { 0 },
+                )
+            })
+        };
+
+        let before = try!(sample());
+        let mut remaining = timeout.num_milliseconds();
+
+        while remaining > 0 {
+            thread::sleep_ms(POLL_INTERVAL_MS);
+            remaining -= POLL_INTERVAL_MS as i64;
+
+            if try!(sample()) != before {
+                return Ok(())
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::TimedOut, "no modem line changed its state"))
+    }
+
+    /// Drives the Data Terminal Ready line
+    pub fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.drive_modem_lines(ffi::TIOCM_DTR, level)
+    }
+
+    /// Drives both the Request To Send and the Data Terminal Ready lines in a single operation
+    pub fn set_rts_dtr(&mut self, rts: bool, dtr: bool) -> io::Result<()> {
+        self.modem_lines().and_then(|mut lines| {
+            lines &= !(ffi::TIOCM_RTS | ffi::TIOCM_DTR);
+
+            if rts {
+                lines |= ffi::TIOCM_RTS;
+            }
+
+            if dtr {
+                lines |= ffi::TIOCM_DTR;
+            }
+
+            let ptr: *const c_int = &lines;
+            unsafe {
+                try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), ffi::TIOCMSET, ptr)));
+            }
+            Ok(())
+        })
+    }
+
+    /// Drives the Request To Send line
+    pub fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.drive_modem_lines(ffi::TIOCM_RTS, level)
+    }
+
+    /// Sets (`level = true`) or clears (`level = false`) the modem control `lines`
+    fn drive_modem_lines(&self, lines: c_int, level: bool) -> io::Result<()> {
+        let request = if level { ffi::TIOCMBIS } else { ffi::TIOCMBIC };
+        let ptr: *const c_int = &lines;
+
+        unsafe {
+            try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), request, ptr)));
+        }
+        Ok(())
+    }
+
+    /// Fetches the state of the modem control lines as a `TIOCM_*` bitmask
+    fn modem_lines(&self) -> io::Result<c_int> {
+        let mut lines: c_int = 0;
+        let ptr: *mut c_int = &mut lines;
+
+        unsafe {
+            try!(ffi::cvt(ffi::ioctl(self.file.as_raw_fd(), ffi::TIOCMGET, ptr)));
+        }
+        Ok(lines)
+    }
+}
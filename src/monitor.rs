@@ -0,0 +1,157 @@
+use std::fs::File;
+use std::io::{Read, self};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use enumerate::{PortInfo, self};
+use ffi;
+
+/// A change in the set of serial ports present in the system
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortEvent {
+    /// A serial port was plugged in
+    Added(PortInfo),
+    /// The serial port with this device path was unplugged
+    Removed(PathBuf),
+}
+
+/// Watches serial ports being plugged in and unplugged
+///
+/// The monitor listens to the uevents that the kernel broadcasts over netlink, so it works
+/// without udev. Iterating over the monitor blocks until the next event.
+pub struct PortMonitor {
+    buf: Vec<u8>,
+    socket: File,
+}
+
+impl PortMonitor {
+    /// Starts listening to uevents
+    pub fn new() -> io::Result<PortMonitor> {
+        let fd = try!(ffi::cvt(unsafe {
+            ffi::socket(
+                ffi::AF_NETLINK,
+                ffi::SOCK_DGRAM | ffi::SOCK_CLOEXEC,
+                ffi::NETLINK_KOBJECT_UEVENT,
+            )
+        }));
+        // Closes the socket on error
+        let socket = unsafe { File::from_raw_fd(fd) };
+
+        let addr = ffi::sockaddr_nl {
+            nl_family: ffi::AF_NETLINK as u16,
+            nl_pad: 0,
+            nl_pid: 0,
+            // Multicast group of the kernel uevents
+            nl_groups: 1,
+        };
+        let len = mem::size_of::<ffi::sockaddr_nl>() as u32;
+        try!(ffi::cvt(unsafe { ffi::bind(fd, &addr, len) }));
+
+        Ok(PortMonitor {
+            buf: vec![0; 8192],
+            socket: socket,
+        })
+    }
+
+    /// Blocks until a serial port is plugged in or unplugged
+    pub fn next_event(&mut self) -> io::Result<PortEvent> {
+        loop {
+            // Each read returns a whole uevent
+            let n = try!(self.socket.read(&mut self.buf));
+
+            if let Some(uevent) = parse(&self.buf[..n]) {
+                if let Some(event) = event(&uevent, Path::new("/sys"), Path::new("/dev")) {
+                    return Ok(event)
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for PortMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl Iterator for PortMonitor {
+    type Item = io::Result<PortEvent>;
+
+    fn next(&mut self) -> Option<io::Result<PortEvent>> {
+        Some(self.next_event())
+    }
+}
+
+/// A kernel uevent
+#[derive(Debug, PartialEq)]
+pub struct Uevent<'a> {
+    /// e.g. `add`, `remove`, `change`
+    pub action: &'a str,
+    /// Name of the device node, relative to `/dev`
+    pub devname: Option<&'a str>,
+    /// Path of the device, relative to `/sys`
+    pub devpath: &'a str,
+    /// e.g. `tty`, `usb`
+    pub subsystem: Option<&'a str>,
+}
+
+/// Parses a uevent, e.g. `add@/devices/...\0ACTION=add\0DEVPATH=/devices/...\0...`
+///
+/// Returns `None` if `buf` is not a kernel uevent.
+pub fn parse(buf: &[u8]) -> Option<Uevent> {
+    let mut fields = buf.split(|&byte| byte == 0).filter_map(|field| str::from_utf8(field).ok());
+
+    let (action, devpath) = match fields.next().map(|header| header.splitn(2, '@')) {
+        None => return None,
+        Some(mut header) => match (header.next(), header.next()) {
+            (Some(action), Some(devpath)) => (action, devpath),
+            _ => return None,
+        },
+    };
+
+    let mut uevent = Uevent {
+        action: action,
+        devname: None,
+        devpath: devpath,
+        subsystem: None,
+    };
+
+    for field in fields {
+        let mut pair = field.splitn(2, '=');
+
+        match (pair.next(), pair.next()) {
+            (Some("ACTION"), Some(action)) => uevent.action = action,
+            (Some("DEVNAME"), Some(devname)) => uevent.devname = Some(devname),
+            (Some("DEVPATH"), Some(devpath)) => uevent.devpath = devpath,
+            (Some("SUBSYSTEM"), Some(subsystem)) => uevent.subsystem = Some(subsystem),
+            _ => {},
+        }
+    }
+
+    Some(uevent)
+}
+
+/// Turns a uevent into a `PortEvent`, looking up added ports in the `sys` sysfs tree
+///
+/// Returns `None` if the uevent is not about a serial port being plugged in or unplugged.
+pub fn event(uevent: &Uevent, sys: &Path, dev: &Path) -> Option<PortEvent> {
+    if uevent.subsystem != Some("tty") {
+        return None
+    }
+
+    let name = match uevent.devname.or_else(|| uevent.devpath.rsplit('/').next()) {
+        None | Some("") => return None,
+        Some(name) => name,
+    };
+
+    match uevent.action {
+        "add" => enumerate::port_info(sys, dev, name).map(PortEvent::Added),
+        // The device is gone from sysfs, but virtual ttys are never backed by a serial port
+        "remove" if !uevent.devpath.starts_with("/devices/virtual/") => {
+            Some(PortEvent::Removed(dev.join(name)))
+        },
+        _ => None,
+    }
+}
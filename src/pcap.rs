@@ -0,0 +1,104 @@
+//! Exporting traffic to the pcap format
+//!
+//! pcap has no link type for serial lines, the packets use the `LINKTYPE_USER0` (147) link type
+//! with a one byte pseudo-header telling the direction of the data: `0` for received (read)
+//! data, `1` for sent (written) data. To dissect the payload in Wireshark, add an entry for
+//! `User 0 (DLT=147)` in the "DLT_USER" protocol preferences, with a header size of 1 and the
+//! protocol spoken over the serial line as the payload protocol.
+
+use std::io::{Read, Write, self};
+use std::time::Duration;
+
+use capture::{CaptureReader, CaptureSink, Record};
+
+/// `LINKTYPE_USER0`
+const LINKTYPE: u32 = 147;
+/// The largest packet stored whole, bigger packets are truncated
+const SNAPLEN: u32 = 65535;
+
+const RECEIVED: u8 = 0;
+const SENT: u8 = 1;
+
+/// Writes the traffic of a serial port as a pcap file
+///
+/// Each chunk of data read from or written to the port becomes a packet. Configuration changes
+/// are not exported.
+pub struct PcapWriter<W: Write> {
+    start: Duration,
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the pcap header, with `start` as the wall-clock time since the Unix epoch at which
+    /// the recording started
+    pub fn new(mut writer: W, start: Duration) -> io::Result<PcapWriter<W>> {
+        let mut header = Vec::with_capacity(24);
+        put_u32(&mut header, 0xA1B2C3D4);
+        put_u16(&mut header, 2);
+        put_u16(&mut header, 4);
+        // Timestamps are in UTC
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, SNAPLEN);
+        put_u32(&mut header, LINKTYPE);
+        try!(writer.write_all(&header));
+
+        Ok(PcapWriter {
+            start: start,
+            writer: writer,
+        })
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> CaptureSink for PcapWriter<W> {
+    fn write_record(&mut self, time: Duration, record: &Record) -> io::Result<()> {
+        let (direction, data) = match *record {
+            Record::Config(_) => return Ok(()),
+            Record::Read(ref data) => (RECEIVED, data),
+            Record::Write(ref data) => (SENT, data),
+        };
+
+        let micros = (self.start + time).num_microseconds().unwrap_or(0);
+        let len = data.len() as u32 + 1;
+        let incl_len = if len > SNAPLEN { SNAPLEN } else { len };
+
+        let mut packet = Vec::with_capacity(16 + incl_len as usize);
+        put_u32(&mut packet, (micros / 1_000_000) as u32);
+        put_u32(&mut packet, (micros % 1_000_000) as u32);
+        put_u32(&mut packet, incl_len);
+        put_u32(&mut packet, len);
+        packet.push(direction);
+        packet.extend(data[..incl_len as usize - 1].iter().cloned());
+
+        self.writer.write_all(&packet)
+    }
+}
+
+/// Converts a `capture` recorded by a `RecordingPort` into a pcap file
+pub fn capture_to_pcap<R: Read, W: Write>(capture: R, pcap: W) -> io::Result<W> {
+    let reader = try!(CaptureReader::new(capture));
+    let mut writer = try!(PcapWriter::new(pcap, reader.start_time()));
+
+    for record in reader {
+        let (time, record) = try!(record);
+
+        try!(writer.write_record(time, &record));
+    }
+
+    Ok(writer.into_inner())
+}
+
+fn put_u16(buf: &mut Vec<u8>, n: u16) {
+    buf.push(n as u8);
+    buf.push((n >> 8) as u8);
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    put_u16(buf, n as u16);
+    put_u16(buf, (n >> 16) as u16);
+}
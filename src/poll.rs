@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::{Read, Write, self};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+use std::{cmp, i32};
+
+use libc::{self, c_int, c_short};
+
+use clock;
+use ffi;
+
+/// Reads from `file`, failing with `TimedOut` if no data arrives within the `timeout`
+pub fn read(file: &mut File, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+    if timeout.is_some() && !try!(wait(file.as_raw_fd(), ffi::POLLIN, timeout)) {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"))
+    }
+
+    file.read(buf)
+}
+
+/// Writes to `file`, failing with `TimedOut` if the device can't accept data within the
+/// `timeout`
+///
+/// With a `timeout`, the write itself is non-blocking: it may be short, as a tty reports being
+/// writable as soon as its output buffer has some room, and a blocking write would wait for the
+/// whole `buf` to fit, which never happens while flow control stalls the line. `O_NONBLOCK` is
+/// set on the open device for the duration of the write, so other handles to it (see
+/// `SerialPort::try_clone()`) may briefly see it in non-blocking mode.
+pub fn write(file: &mut File, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
+    let fd = file.as_raw_fd();
+
+    if timeout.is_none() {
+        return file.write(buf)
+    }
+
+    if !try!(wait(fd, ffi::POLLOUT, timeout)) {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"))
+    }
+
+    let flags = try!(get_flags(fd));
+
+    if flags & ffi::O_NONBLOCK != 0 {
+        return file.write(buf)
+    }
+
+    try!(set_flags(fd, flags | ffi::O_NONBLOCK));
+    let result = file.write(buf);
+    try!(set_flags(fd, flags));
+
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"))
+        },
+        result => result,
+    }
+}
+
+/// Blocks until `fd` is ready for the given `events` (`POLLIN` and/or `POLLOUT`)
+///
+/// Returns `false` if the `timeout` elapsed first. Errors and hang ups of the device count as
+/// ready, so the subsequent `read()`/`write()` call reports them.
+pub fn wait(fd: RawFd, events: c_short, timeout: Option<Duration>) -> io::Result<bool> {
+    let deadline = timeout.map(|timeout| clock::monotonic() + timeout);
+    let mut fds = ffi::pollfd {
+        fd: fd,
+        events: events,
+        revents: 0,
+    };
+
+    loop {
+        // A signal restarts the wait with whatever is left of the `timeout`
+        let remaining = deadline.map(|deadline| deadline - clock::monotonic());
+
+        match ffi::cvt(unsafe { ffi::poll(&mut fds, 1, millis(remaining)) }) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EINTR) => continue,
+            Err(e) => return Err(e),
+            Ok(n) => return Ok(n > 0),
+        }
+    }
+}
+
+/// Whether the device behind `fd` hung up, e.g. because it was unplugged
+pub fn hung_up(fd: RawFd) -> io::Result<bool> {
+    let mut fds = ffi::pollfd {
+        fd: fd,
+        events: 0,
+        revents: 0,
+    };
+
+    try!(ffi::cvt(unsafe { ffi::poll(&mut fds, 1, 0) }));
+
+    Ok(fds.revents & ffi::POLLHUP != 0)
+}
+
+/// Sets or clears the `O_NONBLOCK` flag of `fd`
+pub fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    let flags = try!(get_flags(fd));
+
+    if nonblocking {
+        set_flags(fd, flags | ffi::O_NONBLOCK)
+    } else {
+        set_flags(fd, flags & !ffi::O_NONBLOCK)
+    }
+}
+
+/// Converts `timeout` to the representation used by `poll(2)`
+///
+/// Fractions of a millisecond are rounded up, so short timeouts don't turn into a zero timeout.
+pub fn millis(timeout: Option<Duration>) -> c_int {
+    match timeout {
+        None => -1,
+        Some(timeout) => {
+            let mut ms = timeout.num_milliseconds();
+
+            if timeout > Duration::milliseconds(ms) {
+                ms += 1;
+            }
+
+            let ms = cmp::max(ms, 0);
+
+            cmp::min(ms, i32::MAX as i64) as c_int
+        },
+    }
+}
+
+/// Returns the file status flags of `fd`
+fn get_flags(fd: RawFd) -> io::Result<c_int> {
+    ffi::cvt(unsafe { ffi::fcntl(fd, ffi::F_GETFL) })
+}
+
+fn set_flags(fd: RawFd, flags: c_int) -> io::Result<()> {
+    try!(ffi::cvt(unsafe { ffi::fcntl(fd, ffi::F_SETFL, flags) }));
+    Ok(())
+}
@@ -0,0 +1,167 @@
+//! Virtual serial ports
+//!
+//! A `PtyPair` works like a null modem cable between two serial ports, which is handy to test
+//! protocol code without hardware.
+
+use std::fs::{File, self};
+use std::io::{ErrorKind, Read, Write, self};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::thread::{JoinHandle, self};
+
+use libc::{c_int, c_uint};
+
+use ffi;
+use poll;
+use {OpenOptions, SerialPort};
+
+/// Two virtual serial ports connected to each other
+///
+/// Each port is the slave side of a pseudo terminal. A background thread relays the data
+/// written to one port to the other port, the modem control lines and breaks are not relayed.
+/// The ports stay open in the background until the pair is dropped, so data sent to a port
+/// before it's opened is not lost, and the exclusive mode (see `OpenOptions::exclusive()`) lasts
+/// until the pair is dropped.
+///
+/// Dropping the pair hangs up both ports, like unplugging a USB adapter.
+pub struct PtyPair {
+    ports: (PathBuf, PathBuf),
+    relay: Option<JoinHandle<()>>,
+    stop: Option<File>,
+}
+
+/// Creates two virtual serial ports connected to each other
+pub fn virtual_pair() -> io::Result<PtyPair> {
+    let (first, first_path) = try!(open_pty());
+    let (second, second_path) = try!(open_pty());
+
+    // Holding the ports open keeps the masters from reporting hang ups
+    let first_port = try!(OpenOptions::new().read(true).write(true).open(&first_path));
+    let second_port = try!(OpenOptions::new().read(true).write(true).open(&second_path));
+
+    let (stop_rx, stop_tx) = try!(pipe());
+
+    try!(poll::set_nonblocking(first.as_raw_fd(), true));
+    try!(poll::set_nonblocking(second.as_raw_fd(), true));
+
+    let relay = thread::spawn(move || {
+        let _ports = (first_port, second_port);
+
+        relay([first, second], stop_rx);
+    });
+
+    Ok(PtyPair {
+        ports: (first_path, second_path),
+        relay: Some(relay),
+        stop: Some(stop_tx),
+    })
+}
+
+impl PtyPair {
+    /// Opens both ports with read and write permissions
+    pub fn open(&self) -> io::Result<(SerialPort, SerialPort)> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true);
+
+        Ok((try!(options.open(&self.ports.0)), try!(options.open(&self.ports.1))))
+    }
+
+    /// Returns the paths of the ports, e.g. `/dev/pts/3`
+    pub fn ports(&self) -> (&Path, &Path) {
+        (&self.ports.0, &self.ports.1)
+    }
+}
+
+impl Drop for PtyPair {
+    fn drop(&mut self) {
+        // Closing the pipe wakes up the relay thread, which then closes the masters
+        drop(self.stop.take());
+
+        if let Some(relay) = self.relay.take() {
+            let _ = relay.join();
+        }
+    }
+}
+
+/// Copies data between the `masters` until the `stop` pipe is closed
+fn relay(masters: [File; 2], stop: File) {
+    // `pending[i]` holds the data read from `masters[i]` not yet written to the other master
+    let mut pending = [Vec::new(), Vec::new()];
+    let mut buf = [0; 4096];
+
+    loop {
+        let mut fds = [
+            ffi::pollfd { fd: masters[0].as_raw_fd(), events: 0, revents: 0 },
+            ffi::pollfd { fd: masters[1].as_raw_fd(), events: 0, revents: 0 },
+            ffi::pollfd { fd: stop.as_raw_fd(), events: ffi::POLLIN, revents: 0 },
+        ];
+
+        for i in 0..2 {
+            // Stop reading while the other side can't keep up
+            if pending[i].is_empty() {
+                fds[i].events |= ffi::POLLIN;
+            } else {
+                fds[1 - i].events |= ffi::POLLOUT;
+            }
+        }
+
+        match ffi::cvt(unsafe { ffi::poll(fds.as_mut_ptr(), 3, -1) }) {
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return,
+            Ok(_) => {},
+        }
+
+        if fds[2].revents != 0 {
+            return
+        }
+
+        for i in 0..2 {
+            if fds[i].revents & ffi::POLLIN != 0 {
+                match (&masters[i]).read(&mut buf) {
+                    Err(ref e) if would_block(e) => {},
+                    Err(_) | Ok(0) => return,
+                    Ok(n) => pending[i].extend(buf[..n].iter().cloned()),
+                }
+            }
+
+            if fds[1 - i].revents & ffi::POLLOUT != 0 {
+                match (&masters[1 - i]).write(&pending[i]) {
+                    Err(ref e) if would_block(e) => {},
+                    Err(_) => return,
+                    Ok(n) => pending[i] = pending[i][n..].to_vec(),
+                }
+            }
+        }
+    }
+}
+
+/// Opens a new pseudo terminal, returns its master and the path of its slave
+fn open_pty() -> io::Result<(File, PathBuf)> {
+    let master = try!(fs::OpenOptions::new().read(true).write(true).open("/dev/ptmx"));
+    let fd = master.as_raw_fd();
+    let unlock: c_int = 0;
+    let mut number: c_uint = 0;
+
+    unsafe {
+        let ptr: *const c_int = &unlock;
+        try!(ffi::cvt(ffi::ioctl(fd, ffi::TIOCSPTLCK, ptr)));
+
+        let ptr: *mut c_uint = &mut number;
+        try!(ffi::cvt(ffi::ioctl(fd, ffi::TIOCGPTN, ptr)));
+    }
+
+    Ok((master, PathBuf::from(format!("/dev/pts/{}", number))))
+}
+
+/// Creates a pipe, returns its read and write ends
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+
+    try!(ffi::cvt(unsafe { ffi::pipe2(fds.as_mut_ptr(), ffi::O_CLOEXEC) }));
+
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+fn would_block(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted
+}
@@ -0,0 +1,298 @@
+//! Automatic reconnection
+//!
+//! USB adapters vanish when unplugged, reset or browned out, and come back moments later, maybe
+//! under a different device node. A `ReconnectingPort` notices the lost device on the next
+//! `read()`/`write()`, reopens it and restores its configuration.
+
+use std::cmp;
+use std::io::{Read, Write, self};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use enumerate;
+use ffi;
+use poll;
+use {OpenOptions, Rs485Config, SerialPort};
+
+/// A change in the state of the link of a `ReconnectingPort`
+#[derive(Debug)]
+pub enum LinkEvent {
+    /// The device was lost, with the error that revealed it
+    Disconnected(io::Error),
+    /// A reconnection attempt failed, with the number of the attempt and the error
+    Failed(u32, io::Error),
+    /// The device was reopened at this path and its configuration restored
+    Reconnected(PathBuf),
+}
+
+/// A serial port that reopens its device when it disappears
+///
+/// The configuration of the device, i.e. its termios state and RS-485 mode, and the timeout are
+/// remembered and restored after each reconnection. Reconnecting takes place inside the
+/// `read()`/`write()` call that hit the lost device; if it succeeds the operation is retried on
+/// the new device, otherwise the error of the last attempt is returned. Data in flight when the
+/// device was lost is gone.
+///
+/// The old device is closed before reconnecting, so it doesn't get in the way of reopening it
+/// in exclusive mode (see `OpenOptions::exclusive()`). If every attempt fails, the port stays
+/// disconnected and the next `read()`/`write()` call tries to reconnect again.
+pub struct ReconnectingPort {
+    attempts: u32,
+    drain_on_flush: bool,
+    interval: Duration,
+    listener: Option<Box<FnMut(&LinkEvent)>>,
+    options: OpenOptions,
+    path: PathBuf,
+    port: Option<SerialPort>,
+    rs485: Rs485Config,
+    state: ffi::termios2,
+    timeout: Option<Duration>,
+    usb_serial_number: Option<String>,
+}
+
+impl ReconnectingPort {
+    /// Opens the serial device at `path` with `options`
+    ///
+    /// Reconnecting is attempted 10 times, one second apart, by default.
+    pub fn open<P: AsRef<Path>>(options: &OpenOptions, path: P) -> io::Result<ReconnectingPort> {
+        let path = path.as_ref();
+        let port = try!(options.open(path));
+        let rs485 = try!(port.rs485());
+        let state = try!(port.fetch2());
+
+        Ok(ReconnectingPort {
+            attempts: 10,
+            drain_on_flush: port.drain_on_flush(),
+            interval: Duration::seconds(1),
+            listener: None,
+            options: options.clone(),
+            path: path.to_path_buf(),
+            timeout: port.timeout(),
+            port: Some(port),
+            rs485: rs485,
+            state: state,
+            usb_serial_number: None,
+        })
+    }
+
+    /// Changes the configuration of the serial port
+    ///
+    /// Changes made through `f` are remembered and restored after a reconnection. If the port is
+    /// disconnected, reconnecting is attempted first.
+    pub fn configure<F, T>(&mut self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut SerialPort) -> io::Result<T>,
+    {
+        let (ret, drain_on_flush, rs485, state, timeout) = {
+            let port = try!(self.connected());
+            let ret = try!(f(port));
+
+            (ret, port.drain_on_flush(), try!(port.rs485()), try!(port.fetch2()), port.timeout())
+        };
+
+        self.drain_on_flush = drain_on_flush;
+        self.rs485 = rs485;
+        self.state = state;
+        self.timeout = timeout;
+
+        Ok(ret)
+    }
+
+    /// Returns a reference to the underlying serial port, or `None` while disconnected
+    ///
+    /// The underlying port is replaced on each reconnection.
+    pub fn get_ref(&self) -> Option<&SerialPort> {
+        self.port.as_ref()
+    }
+
+    /// Registers a `listener` that gets notified of disconnections and reconnections
+    ///
+    /// The listener runs inside the `read()`/`write()` call that hit the lost device.
+    pub fn on_event<F>(&mut self, listener: F) where F: FnMut(&LinkEvent) + 'static {
+        self.listener = Some(Box::new(listener));
+    }
+
+    /// Returns the path of the device currently in use
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Closes the device and reopens it right away
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        let mut error = None;
+
+        // Otherwise the exclusive mode and the lock file of the old device would make reopening
+        // it fail
+        self.port = None;
+
+        for attempt in 0..self.attempts {
+            if attempt != 0 {
+                thread::sleep_ms(cmp::max(self.interval.num_milliseconds(), 0) as u32);
+            }
+
+            match self.reopen() {
+                Err(e) => {
+                    self.notify(LinkEvent::Failed(attempt + 1, clone_error(&e)));
+                    error = Some(e);
+                },
+                Ok((path, port)) => {
+                    self.notify(LinkEvent::Reconnected(path.clone()));
+                    self.path = path;
+                    self.port = Some(port);
+                    return Ok(())
+                },
+            }
+        }
+
+        Err(error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "no reconnection attempts allowed")
+        }))
+    }
+
+    /// Changes how many times and how often reconnecting is attempted
+    pub fn set_retry(&mut self, attempts: u32, interval: Duration) {
+        self.attempts = attempts;
+        self.interval = interval;
+    }
+
+    /// Reconnects to the USB adapter with this serial number, wherever it shows up
+    ///
+    /// Unplugging an adapter and plugging it back may change its device node, e.g. from
+    /// `/dev/ttyUSB0` to `/dev/ttyUSB1`. With a serial number set, the device is looked up among
+    /// the `available_ports()` instead of being reopened at the same path.
+    pub fn set_usb_serial_number(&mut self, serial_number: Option<String>) {
+        self.usb_serial_number = serial_number;
+    }
+
+    /// Returns the port, reconnecting first if it's disconnected
+    fn connected(&mut self) -> io::Result<&mut SerialPort> {
+        if self.port.is_none() {
+            try!(self.reconnect());
+        }
+
+        Ok(self.port.as_mut().unwrap())
+    }
+
+    /// Reconnects if `error` means that the device is gone, otherwise passes `error` through
+    fn recover(&mut self, error: io::Error) -> io::Result<()> {
+        if !is_disconnection(&error) {
+            return Err(error)
+        }
+
+        self.notify(LinkEvent::Disconnected(error));
+        self.reconnect()
+    }
+
+    /// Handles an end of file condition, which is how some drivers report a hang up
+    fn recover_eof(&mut self) -> io::Result<bool> {
+        let fd = match self.port {
+            None => return Ok(false),
+            Some(ref port) => port.as_raw_fd(),
+        };
+
+        if !try!(poll::hung_up(fd)) {
+            return Ok(false)
+        }
+
+        try!(self.recover(io::Error::from_raw_os_error(libc::EIO)));
+        Ok(true)
+    }
+
+    fn notify(&mut self, event: LinkEvent) {
+        if let Some(ref mut listener) = self.listener {
+            (*listener)(&event);
+        }
+    }
+
+    fn reopen(&self) -> io::Result<(PathBuf, SerialPort)> {
+        let path = match self.usb_serial_number {
+            None => self.path.clone(),
+            Some(ref serial_number) => {
+                let ports = try!(enumerate::available_ports());
+                let info = ports.into_iter().find(|info| {
+                    info.usb.as_ref().and_then(|usb| usb.serial_number.as_ref()) ==
+                        Some(serial_number)
+                });
+
+                match info {
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "no USB serial port with the requested serial number",
+                        ))
+                    },
+                    Some(info) => info.path,
+                }
+            },
+        };
+
+        let mut port = try!(self.options.open(&path));
+
+        try!(port.update2(&self.state));
+        port.set_drain_on_flush(self.drain_on_flush);
+        port.set_timeout(self.timeout);
+
+        if self.rs485.enabled {
+            try!(port.set_rs485(self.rs485));
+        }
+
+        Ok((path, port))
+    }
+}
+
+impl Read for ReconnectingPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = try!(self.connected()).read(buf);
+
+        match result {
+            Err(e) => try!(self.recover(e)),
+            Ok(0) if !buf.is_empty() => if !try!(self.recover_eof()) { return Ok(0) },
+            Ok(n) => return Ok(n),
+        }
+
+        try!(self.connected()).read(buf)
+    }
+}
+
+impl Write for ReconnectingPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = try!(self.connected()).write(buf);
+
+        match result {
+            Err(e) => try!(self.recover(e)),
+            Ok(n) => return Ok(n),
+        }
+
+        try!(self.connected()).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = try!(self.connected()).flush();
+
+        match result {
+            Err(e) => try!(self.recover(e)),
+            Ok(()) => return Ok(()),
+        }
+
+        try!(self.connected()).flush()
+    }
+}
+
+/// Whether `error` means that the device is gone
+fn is_disconnection(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(libc::EIO) | Some(libc::ENODEV) | Some(libc::ENXIO) => true,
+        _ => false,
+    }
+}
+
+/// `io::Error` is not `Clone`, the listener gets a copy that preserves the OS error code
+fn clone_error(error: &io::Error) -> io::Error {
+    match error.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(error.kind(), error.to_string()),
+    }
+}
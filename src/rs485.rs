@@ -0,0 +1,243 @@
+use std::cmp;
+use std::io::{ErrorKind, self};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use ffi;
+use poll;
+use SerialPort;
+
+/// RS-485 half-duplex configuration
+///
+/// An RS-485 transceiver must only drive the bus while transmitting, its driver is usually
+/// enabled through the RTS line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rs485Config {
+    /// How long to wait after the end of a transmission before driving RTS back to
+    /// `rts_after_send`
+    pub delay_after_send: Duration,
+    /// How long to wait after driving RTS to `rts_on_send` before transmitting
+    pub delay_before_send: Duration,
+    /// Whether the RS-485 mode is enabled
+    pub enabled: bool,
+    /// Level of RTS while idle, i.e. after a transmission
+    pub rts_after_send: bool,
+    /// Level of RTS during a transmission
+    pub rts_on_send: bool,
+    /// Whether data is received while transmitting, e.g. the echo of the transmitted data
+    pub rx_during_tx: bool,
+}
+
+impl Rs485Config {
+    /// RS-485 mode enabled, RTS asserted during transmissions, with no delays and without
+    /// receiving while transmitting
+    pub fn new() -> Rs485Config {
+        Rs485Config {
+            delay_after_send: Duration::zero(),
+            delay_before_send: Duration::zero(),
+            enabled: true,
+            rts_after_send: false,
+            rts_on_send: true,
+            rx_during_tx: false,
+        }
+    }
+
+    /// RS-485 mode disabled
+    pub fn disabled() -> Rs485Config {
+        Rs485Config {
+            enabled: false,
+            .. Rs485Config::new()
+        }
+    }
+}
+
+impl SerialPort {
+    /// Returns the RS-485 configuration of the port
+    ///
+    /// Returns a disabled configuration if the driver doesn't support the RS-485 mode.
+    pub fn rs485(&self) -> io::Result<Rs485Config> {
+        if let Some(config) = self.rs485 {
+            return Ok(config)
+        }
+
+        let mut rs485: ffi::serial_rs485 = unsafe { mem::zeroed() };
+        let ptr: *mut ffi::serial_rs485 = &mut rs485;
+
+        match ffi::cvt(unsafe { ffi::ioctl(self.file.as_raw_fd(), ffi::TIOCGRS485, ptr) }) {
+            Err(ref e) if ffi::unsupported(e) => Ok(Rs485Config::disabled()),
+            Err(e) => Err(e),
+            Ok(_) => Ok(Rs485Config {
+                delay_after_send: Duration::milliseconds(rs485.delay_rts_after_send as i64),
+                delay_before_send: Duration::milliseconds(rs485.delay_rts_before_send as i64),
+                enabled: rs485.flags & ffi::SER_RS485_ENABLED != 0,
+                rts_after_send: rs485.flags & ffi::SER_RS485_RTS_AFTER_SEND != 0,
+                rts_on_send: rs485.flags & ffi::SER_RS485_RTS_ON_SEND != 0,
+                rx_during_tx: rs485.flags & ffi::SER_RS485_RX_DURING_TX != 0,
+            }),
+        }
+    }
+
+    /// Changes the RS-485 configuration of the port
+    ///
+    /// The driver toggles RTS around transmissions if it supports the RS-485 mode. Otherwise
+    /// the RS-485 mode is emulated: each `write()` drives RTS, transmits the whole buffer, waits
+    /// until it has been physically sent and drives RTS back, which makes `write()` block until
+    /// the end of the transmission. Unless `rx_during_tx` is set, the echo of the transmitted
+    /// data is dropped. The emulation can't meet tight bus turnaround times.
+    pub fn set_rs485(&mut self, config: Rs485Config) -> io::Result<()> {
+        let mut flags = 0;
+
+        if config.enabled {
+            flags |= ffi::SER_RS485_ENABLED;
+        }
+
+        if config.rts_on_send {
+            flags |= ffi::SER_RS485_RTS_ON_SEND;
+        }
+
+        if config.rts_after_send {
+            flags |= ffi::SER_RS485_RTS_AFTER_SEND;
+        }
+
+        if config.rx_during_tx {
+            flags |= ffi::SER_RS485_RX_DURING_TX;
+        }
+
+        let rs485 = ffi::serial_rs485 {
+            flags: flags,
+            delay_rts_before_send: config.delay_before_send.num_milliseconds() as u32,
+            delay_rts_after_send: config.delay_after_send.num_milliseconds() as u32,
+            padding: [0; 5],
+        };
+        let ptr: *const ffi::serial_rs485 = &rs485;
+
+        match ffi::cvt(unsafe { ffi::ioctl(self.file.as_raw_fd(), ffi::TIOCSRS485, ptr) }) {
+            Err(ref e) if ffi::unsupported(e) => {
+                if config.enabled {
+                    // Release the bus right away
+                    try!(self.set_rts(config.rts_after_send));
+                    self.rs485 = Some(config);
+                } else {
+                    self.rs485 = None;
+                }
+
+                Ok(())
+            },
+            Err(e) => Err(e),
+            Ok(_) => {
+                self.rs485 = None;
+                Ok(())
+            },
+        }
+    }
+}
+
+/// The operations needed to emulate the RS-485 mode
+pub trait Transceiver {
+    /// Returns the number of bytes received but not read yet
+    fn bytes_to_read(&self) -> io::Result<usize>;
+    /// Blocks until all the written data has been transmitted
+    fn drain(&mut self) -> io::Result<()>;
+    /// Reads `n` bytes that have already been received
+    fn read_received(&mut self, n: usize) -> io::Result<Vec<u8>>;
+    /// Drives the RTS line
+    fn set_rts(&mut self, level: bool) -> io::Result<()>;
+    /// Writes the whole `buf`
+    fn transmit(&mut self, buf: &[u8]) -> io::Result<()>;
+    /// Puts back received data, to be returned by the next reads
+    fn unread(&mut self, data: Vec<u8>);
+}
+
+impl Transceiver for SerialPort {
+    fn bytes_to_read(&self) -> io::Result<usize> {
+        // Leaves out the data put back by `unread()`, which is not in the input queue
+        self.queue_len(ffi::FIONREAD)
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        SerialPort::drain(self)
+    }
+
+    fn read_received(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; n];
+        let mut read = 0;
+
+        // The data is already there, so exact reads never wait for the blocking mode
+        while read < n {
+            match poll::read(&mut self.file, &mut data[read..], None) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+                Ok(0) => return Err(io::Error::new(ErrorKind::Other, "received data vanished")),
+                Ok(m) => read += m,
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        SerialPort::set_rts(self, level)
+    }
+
+    fn transmit(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match poll::write(&mut self.file, buf, self.timeout) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+                Ok(0) => {
+                    let msg = "failed to write whole buffer";
+
+                    return Err(io::Error::new(ErrorKind::WriteZero, msg))
+                },
+                Ok(n) => buf = &buf[n..],
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unread(&mut self, data: Vec<u8>) {
+        // Data put back earlier was received first
+        self.unread.extend(data.into_iter());
+    }
+}
+
+/// Transmits `buf` as a single RS-485 frame, driving RTS as specified by `config`
+///
+/// Unless `config.rx_during_tx` is set, the echo of the frame, i.e. the data received while
+/// driving the bus, up to `buf.len()` bytes, is dropped. Data received before the frame and
+/// replies that follow the echo are kept.
+pub fn write<T: Transceiver>(port: &mut T, buf: &[u8], config: &Rs485Config) -> io::Result<usize> {
+    let pending = if config.rx_during_tx { 0 } else { try!(port.bytes_to_read()) };
+
+    try!(port.set_rts(config.rts_on_send));
+    sleep(config.delay_before_send);
+
+    let result = port.transmit(buf).and_then(|_| port.drain());
+
+    // The bus is released even if the transmission failed
+    sleep(config.delay_after_send);
+    let echo = if config.rx_during_tx { Ok(0) } else { port.bytes_to_read() };
+    try!(port.set_rts(config.rts_after_send));
+    try!(result);
+
+    let echo = cmp::min(try!(echo).saturating_sub(pending), buf.len());
+
+    if echo != 0 {
+        let mut received = try!(port.read_received(pending + echo));
+        received.truncate(pending);
+        port.unread(received);
+    }
+
+    Ok(buf.len())
+}
+
+fn sleep(duration: Duration) {
+    let ms = duration.num_milliseconds();
+
+    if ms > 0 {
+        thread::sleep_ms(ms as u32);
+    }
+}
@@ -0,0 +1,279 @@
+use termios::BaudRate;
+use termios::prelude::*;
+
+use {BlockingMode, DataBits, Direction, FlowControl, Parity, SoftwareFlowControl, StopBits};
+
+/// A complete serial port configuration
+///
+/// Unlike the individual `SerialPort` setters, which update the device once per call, a
+/// `PortSettings` value is applied to the device in a single step. This avoids leaving the line
+/// in a half-configured state while switching between configurations.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PortSettings {
+    /// Input and output baud rates
+    pub baud_rate: (BaudRate, BaudRate),
+    /// Blocking mode used by `read()` calls
+    pub blocking_mode: BlockingMode,
+    /// Number of data bits per character
+    pub data_bits: DataBits,
+    /// Flow control
+    pub flow_control: FlowControl,
+    /// Bit parity
+    pub parity: Parity,
+    /// Number of stop bits per character
+    pub stop_bits: StopBits,
+}
+
+impl PortSettings {
+    /// Creates a "9600 8N1" configuration without flow control
+    ///
+    /// `read()` calls will block until at least one byte is received.
+    pub fn new() -> PortSettings {
+        PortSettings {
+            baud_rate: (BaudRate::B9600, BaudRate::B9600),
+            blocking_mode: BlockingMode { bytes: 1, deciseconds: 0 },
+            data_bits: DataBits::Eight,
+            flow_control: FlowControl::None,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+
+    /// Changes the baud rate of the input/output or both directions
+    pub fn set_baud_rate(&mut self, direction: Direction, rate: BaudRate) -> &mut PortSettings {
+        match direction {
+            Direction::Both => self.baud_rate = (rate, rate),
+            Direction::Input => self.baud_rate.0 = rate,
+            Direction::Output => self.baud_rate.1 = rate,
+        }
+        self
+    }
+
+    /// Changes the blocking mode
+    pub fn set_blocking_mode(&mut self, mode: BlockingMode) -> &mut PortSettings {
+        self.blocking_mode = mode;
+        self
+    }
+
+    /// Changes the number of data bits per character
+    pub fn set_data_bits(&mut self, bits: DataBits) -> &mut PortSettings {
+        self.data_bits = bits;
+        self
+    }
+
+    /// Changes the flow control
+    pub fn set_flow_control(&mut self, flow: FlowControl) -> &mut PortSettings {
+        self.flow_control = flow;
+        self
+    }
+
+    /// Changes the bit parity
+    pub fn set_parity(&mut self, parity: Parity) -> &mut PortSettings {
+        self.parity = parity;
+        self
+    }
+
+    /// Changes the number of stop bits per character
+    pub fn set_stop_bits(&mut self, bits: StopBits) -> &mut PortSettings {
+        self.stop_bits = bits;
+        self
+    }
+}
+
+/// Reads the settings stored in a termios structure
+///
+/// The baud rates come from `SerialPort::baud_rate()`, as `Termios` can't represent all of them.
+pub fn fetch(termios: &Termios, baud_rate: (BaudRate, BaudRate)) -> PortSettings {
+    PortSettings {
+        baud_rate: baud_rate,
+        blocking_mode: blocking_mode(termios),
+        data_bits: data_bits(termios),
+        flow_control: flow_control(termios),
+        parity: parity(termios),
+        stop_bits: stop_bits(termios),
+    }
+}
+
+/// Writes `settings` into a termios structure
+pub fn update(termios: &mut Termios, settings: &PortSettings) {
+    let (input, output) = settings.baud_rate;
+
+    set_baud_rate(termios, Direction::Input, input);
+    set_baud_rate(termios, Direction::Output, output);
+    set_blocking_mode(termios, settings.blocking_mode);
+    set_data_bits(termios, settings.data_bits);
+    set_flow_control(termios, settings.flow_control);
+    set_parity(termios, settings.parity);
+    set_stop_bits(termios, settings.stop_bits);
+}
+
+pub fn blocking_mode(termios: &Termios) -> BlockingMode {
+    BlockingMode {
+        bytes: termios.cc[control::Char::VMIN],
+        deciseconds: termios.cc[control::Char::VTIME],
+    }
+}
+
+pub fn data_bits(termios: &Termios) -> DataBits {
+    match termios.get::<control::CSIZE>() {
+        control::CSIZE::CS5 => DataBits::Five,
+        control::CSIZE::CS6 => DataBits::Six,
+        control::CSIZE::CS7 => DataBits::Seven,
+        control::CSIZE::CS8 => DataBits::Eight,
+    }
+}
+
+pub fn flow_control(termios: &Termios) -> FlowControl {
+    let hardware = termios.contains(control::Flag::CRTSCTS);
+    let input = termios.contains(input::Flag::IXOFF);
+    let output = termios.contains(input::Flag::IXON);
+
+    if input || output {
+        let software = SoftwareFlowControl {
+            // `IXANY` has no effect without `IXON`
+            any: output && termios.contains(input::Flag::IXANY),
+            input: input,
+            output: output,
+            xoff: termios.cc[control::Char::VSTOP],
+            xon: termios.cc[control::Char::VSTART],
+        };
+
+        if hardware {
+            FlowControl::Both(software)
+        } else {
+            FlowControl::Software(software)
+        }
+    } else if hardware {
+        FlowControl::Hardware
+    } else {
+        FlowControl::None
+    }
+}
+
+pub fn parity(termios: &Termios) -> Parity {
+    match (
+        termios.contains(control::Flag::PARENB),
+        termios.contains(control::Flag::CMSPAR),
+        termios.contains(control::Flag::PARODD),
+    ) {
+        (true, true, true) => Parity::Mark,
+        (true, true, false) => Parity::Space,
+        (true, false, true) => Parity::Odd,
+        (true, false, false) => Parity::Even,
+        (false, _, _) => Parity::None,
+    }
+}
+
+pub fn stop_bits(termios: &Termios) -> StopBits {
+    if termios.contains(control::Flag::CSTOPB) {
+        StopBits::Two
+    } else {
+        StopBits::One
+    }
+}
+
+pub fn set_baud_rate(termios: &mut Termios, direction: Direction, rate: BaudRate) {
+    match direction {
+        Direction::Both => termios.set_speed(rate),
+        Direction::Input => termios.set_ispeed(rate),
+        Direction::Output => termios.set_ospeed(rate),
+    }
+}
+
+pub fn set_blocking_mode(termios: &mut Termios, mode: BlockingMode) {
+    termios.cc[control::Char::VMIN] = mode.bytes;
+    termios.cc[control::Char::VTIME] = mode.deciseconds;
+}
+
+pub fn set_data_bits(termios: &mut Termios, bits: DataBits) {
+    termios.set(match bits {
+        DataBits::Five => control::CSIZE::CS5,
+        DataBits::Six => control::CSIZE::CS6,
+        DataBits::Seven => control::CSIZE::CS7,
+        DataBits::Eight => control::CSIZE::CS8,
+    });
+}
+
+pub fn set_flow_control(termios: &mut Termios, flow: FlowControl) {
+    match flow {
+        FlowControl::Both(software) => {
+            termios.set(control::Flag::CRTSCTS);
+            set_software_flow_control(termios, software);
+        },
+        FlowControl::Hardware => {
+            termios.clear(input::Flag::IXANY);
+            termios.clear(input::Flag::IXOFF);
+            termios.clear(input::Flag::IXON);
+            termios.set(control::Flag::CRTSCTS);
+        },
+        FlowControl::None => {
+            termios.clear(control::Flag::CRTSCTS);
+            termios.clear(input::Flag::IXANY);
+            termios.clear(input::Flag::IXOFF);
+            termios.clear(input::Flag::IXON);
+        },
+        FlowControl::Software(software) => {
+            termios.clear(control::Flag::CRTSCTS);
+            set_software_flow_control(termios, software);
+        },
+    }
+}
+
+pub fn set_parity(termios: &mut Termios, parity: Parity) {
+    match parity {
+        Parity::Even => {
+            termios.clear(control::Flag::CMSPAR);
+            termios.clear(control::Flag::PARODD);
+            termios.set(control::Flag::PARENB);
+        },
+        Parity::Mark => {
+            termios.set(control::Flag::CMSPAR);
+            termios.set(control::Flag::PARENB);
+            termios.set(control::Flag::PARODD);
+        },
+        Parity::None => {
+            termios.clear(control::Flag::CMSPAR);
+            termios.clear(control::Flag::PARENB);
+        },
+        Parity::Odd => {
+            termios.clear(control::Flag::CMSPAR);
+            termios.set(control::Flag::PARENB);
+            termios.set(control::Flag::PARODD);
+        },
+        Parity::Space => {
+            termios.clear(control::Flag::PARODD);
+            termios.set(control::Flag::CMSPAR);
+            termios.set(control::Flag::PARENB);
+        },
+    }
+}
+
+pub fn set_stop_bits(termios: &mut Termios, bits: StopBits) {
+    match bits {
+        StopBits::One => termios.clear(control::Flag::CSTOPB),
+        StopBits::Two => termios.set(control::Flag::CSTOPB),
+    }
+}
+
+fn set_software_flow_control(termios: &mut Termios, software: SoftwareFlowControl) {
+    if software.any && software.output {
+        termios.set(input::Flag::IXANY);
+    } else {
+        termios.clear(input::Flag::IXANY);
+    }
+
+    if software.input {
+        termios.set(input::Flag::IXOFF);
+    } else {
+        termios.clear(input::Flag::IXOFF);
+    }
+
+    if software.output {
+        termios.set(input::Flag::IXON);
+    } else {
+        termios.clear(input::Flag::IXON);
+    }
+
+    termios.cc[control::Char::VSTART] = software.xon;
+    termios.cc[control::Char::VSTOP] = software.xoff;
+}
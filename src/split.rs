@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{Read, Write, self};
+use std::{cmp, mem};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Duration;
+
+use events::Decoder;
+use ffi;
+use poll;
+use SerialPort;
+
+/// The reading half of a serial port, see `SerialPort::split()`
+pub struct SerialReader {
+    file: File,
+    timeout: Option<Duration>,
+    unread: Vec<u8>,
+}
+
+impl SerialReader {
+    /// Changes how long `read()` calls may block, see `SerialPort::set_timeout()`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns how long `read()` calls may block
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+impl AsRawFd for SerialReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Read for SerialReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unread.is_empty() {
+            return poll::read(&mut self.file, buf, self.timeout)
+        }
+
+        let n = cmp::min(buf.len(), self.unread.len());
+
+        for (dst, src) in buf.iter_mut().zip(self.unread.iter()) {
+            *dst = *src;
+        }
+
+        self.unread = self.unread[n..].to_vec();
+        Ok(n)
+    }
+}
+
+/// The writing half of a serial port, see `SerialPort::split()`
+pub struct SerialWriter {
+    file: File,
+    timeout: Option<Duration>,
+}
+
+impl SerialWriter {
+    /// Changes how long `write()` calls may block, see `SerialPort::set_timeout()`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns how long `write()` calls may block
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+impl AsRawFd for SerialWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Write for SerialWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        poll::write(&mut self.file, buf, self.timeout)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl SerialPort {
+    /// Splits the serial port into independent reading and writing halves
+    ///
+    /// The halves can be moved to different threads, so reading doesn't block writing and vice
+    /// versa. Both halves start with the timeout of this port. This port stays usable as a
+    /// handle to change the configuration of the device.
+    ///
+    /// All the handles share the same open device, hence the non-blocking mode and the device
+    /// configuration apply to all of them. The lock file, if any, is held by this port.
+    ///
+    /// The data this port already took from the device but didn't hand out yet (e.g. what was
+    /// received while emulating the RS-485 mode) moves to the reading half, which returns it
+    /// first.
+    ///
+    /// Fails with an error of kind `InvalidInput` while the RS-485 mode is emulated (see
+    /// `set_rs485()`), as the writing half wouldn't drive RTS.
+    pub fn split(&mut self) -> io::Result<(SerialReader, SerialWriter)> {
+        if self.rs485.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't split a port that emulates the RS-485 mode",
+            ))
+        }
+
+        let mut reader = SerialReader {
+            file: try!(dup(&self.file)),
+            timeout: self.timeout,
+            unread: Vec::new(),
+        };
+        let writer = SerialWriter {
+            file: try!(dup(&self.file)),
+            timeout: self.timeout,
+        };
+
+        // Only once nothing can fail, so the data isn't lost on error
+        reader.unread = mem::replace(&mut self.unread, Vec::new());
+
+        Ok((reader, writer))
+    }
+
+    /// Creates a new handle to the same serial device
+    ///
+    /// The new handle starts with the timeout of this port. Both handles share the same open
+    /// device, hence the non-blocking mode and the device configuration apply to both of them.
+    /// The lock file, if any, is held by this port.
+    ///
+    /// Fails with an error of kind `InvalidInput` while the RS-485 mode is emulated (see
+    /// `set_rs485()`), as the handles would race for the echo of each other's transmissions.
+    pub fn try_clone(&self) -> io::Result<SerialPort> {
+        if self.rs485.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't clone a port that emulates the RS-485 mode",
+            ))
+        }
+
+        Ok(SerialPort {
+            decoder: Decoder::new(),
+            drain_on_flush: self.drain_on_flush,
+            file: try!(dup(&self.file)),
+            lock: None,
+            rs485: None,
+            timeout: self.timeout,
+            unread: Vec::new(),
+        })
+    }
+}
+
+/// Duplicates the file descriptor of `file`
+fn dup(file: &File) -> io::Result<File> {
+    let fd = try!(ffi::cvt(unsafe {
+        ffi::fcntl(file.as_raw_fd(), ffi::F_DUPFD_CLOEXEC, 0)
+    }));
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
@@ -1,12 +1,29 @@
 #![allow(trivial_casts)]
 
-use std::io::{Read, Write};
-use std::{iter, str};
+use std::fs::{File, self};
+use std::io::{Read, Write, self};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{env, i32, iter, str, thread};
+
+use libc;
+use termios::prelude::*;
 
 use BaudRate::*;
-use {BaudRate, BlockingMode, Direction, OpenOptions, SerialPort};
+use {BaudRate, BlockingMode, Direction, Event, FlowControl, LinkEvent, ModemMask, OpenOptions};
+use {CaptureReader, ConfigChange, LineReader, MockPort, PortEvent, PortInfo, PortSettings};
+use {PortType, ReconnectingPort, Record, RecordingPort, ReplayPort, Rs485Config, Serial};
+use {PcapWriter, SerialPort, SoftwareFlowControl, StopBits, Terminator, UsbInfo};
+use {capture_to_pcap, virtual_pair};
 
-use socat::Socat;
+use enumerate;
+use events::Decoder;
+use lock::LockFile;
+use monitor::{Uevent, self};
+use poll;
+use rs485::{Transceiver, self};
 
 const BAUD_RATES: &'static [BaudRate; 19] = &[
     B0,
@@ -32,10 +49,82 @@ const BAUD_RATES: &'static [BaudRate; 19] = &[
 
 const MESSAGE: &'static str = "Hello World!";
 
+/// Configures `port`, sends a `command` and reads the reply, like protocol code would
+fn query<S: Serial>(port: &mut S, command: &[u8], reply: &mut [u8]) -> io::Result<usize> {
+    try!(port.set_baud_rate(Direction::Both, B115200));
+    port.set_timeout(Some(Duration::seconds(1)));
+    try!(port.write_all(command));
+    port.read(reply)
+}
+
+/// Builds a sysfs tree under `sys` that mimics a system with a few ttys
+fn fake_sysfs(sys: &Path) {
+    let dir = |path: &str| fs::create_dir_all(&sys.join(path)).unwrap();
+    let link = |target: &str, link: &str| {
+        fs::soft_link(&sys.join(target), &sys.join(link)).unwrap()
+    };
+
+    dir("class/tty");
+
+    // Console, not a serial port
+    dir("devices/virtual/tty/tty0");
+    link("devices/virtual/tty/tty0", "class/tty/tty0");
+
+    // Legacy pseudo terminal
+    dir("devices/virtual/tty/ptyp0");
+    link("devices/virtual/tty/ptyp0", "class/tty/ptyp0");
+
+    // Motherboard UART
+    dir("devices/pnp0/00:05/tty/ttyS0");
+    link("devices/pnp0/00:05/tty/ttyS0", "class/tty/ttyS0");
+    link("devices/pnp0/00:05", "devices/pnp0/00:05/tty/ttyS0/device");
+    link("bus/pnp/drivers/serial", "devices/pnp0/00:05/driver");
+    link("bus/pnp", "devices/pnp0/00:05/subsystem");
+
+    // Placeholder port registered by the 8250 driver
+    dir("devices/platform/serial8250/tty/ttyS1");
+    link("devices/platform/serial8250/tty/ttyS1", "class/tty/ttyS1");
+    link("devices/platform/serial8250", "devices/platform/serial8250/tty/ttyS1/device");
+    link("bus/platform/drivers/serial8250", "devices/platform/serial8250/driver");
+    link("bus/platform", "devices/platform/serial8250/subsystem");
+
+    let attr = |path: &str, contents: &str| {
+        File::create(&sys.join(path)).and_then(|mut file| write!(file, "{}\n", contents)).unwrap()
+    };
+
+    // CDC ACM device
+    dir("devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3:1.0");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-3/idVendor", "2341");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-3/idProduct", "0043");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-3/manufacturer", "Arduino (www.arduino.cc)");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3:1.0/bInterfaceNumber", "00");
+    let acm = "devices/pci0000:00/0000:00:14.0/usb1/1-3/1-3:1.0";
+    dir(&format!("{}/tty/ttyACM0", acm));
+    link(&format!("{}/tty/ttyACM0", acm), "class/tty/ttyACM0");
+    link(acm, &format!("{}/tty/ttyACM0/device", acm));
+    link("bus/usb/drivers/cdc_acm", &format!("{}/driver", acm));
+    link("bus/usb", &format!("{}/subsystem", acm));
+
+    // USB to serial adapter
+    dir("devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-2/idVendor", "0403");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-2/idProduct", "6001");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-2/manufacturer", "FTDI");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-2/product", "FT232R USB UART");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-2/serial", "A6008isP");
+    attr("devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/bInterfaceNumber", "00");
+    let ftdi = "devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/ttyUSB0";
+    dir(&format!("{}/tty/ttyUSB0", ftdi));
+    link(&format!("{}/tty/ttyUSB0", ftdi), "class/tty/ttyUSB0");
+    link(ftdi, &format!("{}/tty/ttyUSB0/device", ftdi));
+    link("bus/usb-serial/drivers/ftdi_sio", &format!("{}/driver", ftdi));
+    link("bus/usb-serial", &format!("{}/subsystem", ftdi));
+}
+
 #[test]
 fn bidirectional_baud_rate() {
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
@@ -59,8 +148,8 @@ fn bidirectional_baud_rate() {
 
 #[quickcheck]
 fn blocking_mode(bytes: u8, deciseconds: u8) -> bool {
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
@@ -78,14 +167,109 @@ fn blocking_mode(bytes: u8, deciseconds: u8) -> bool {
     }
 }
 
+#[test]
+fn bytes_to_read() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let mut tx = OpenOptions::new().write(true).open(tx).unwrap();
+    let mut rx = SerialPort::open(rx).unwrap();
+
+    assert_eq!(rx.bytes_to_read().ok(), Some(0));
+
+    tx.write_all(MESSAGE.as_bytes()).unwrap();
+    thread::sleep_ms(100);
+    assert_eq!(rx.bytes_to_read().ok(), Some(MESSAGE.len()));
+
+    let mut buf = [0; 5];
+    rx.read(&mut buf).unwrap();
+    assert_eq!(rx.bytes_to_read().ok(), Some(MESSAGE.len() - buf.len()));
+}
+
+#[test]
+fn bytes_to_write() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let mut tx = OpenOptions::new().write(true).open(tx).unwrap();
+    let _rx = SerialPort::open(rx).unwrap();
+
+    tx.write_all(MESSAGE.as_bytes()).unwrap();
+    assert!(tx.bytes_to_write().unwrap() <= MESSAGE.len());
+
+    tx.drain().unwrap();
+    assert_eq!(tx.bytes_to_write().ok(), Some(0));
+}
+
+#[test]
+fn custom_baud_rate() {
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let port_ = port.display();
+    let mut port = match SerialPort::open(port) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
+        Ok(port) => port,
+    };
+
+    for &rate in &[31250, 115200, 250000, 1000000] {
+        if let Err(e) = port.set_baud_rate_custom(Direction::Both, rate) {
+            panic!("{:?}: Couldn't set both baud rates to {} Hz ({:?})", port_, rate, e)
+        }
+        let got = match port.baud_rate_hz() {
+            Err(e) => panic!("{:?}: Couldn't read baud rate ({:?})", port_, e),
+            Ok(rate) => rate,
+        };
+
+        if (rate, rate) != got {
+            panic!("{:?}: set {:?} - got {:?}", port_, rate, got);
+        }
+    }
+
+    // Baud rates without a `BaudRate` variant are not misreported
+    let error = port.settings().err().map(|e| e.kind());
+    assert_eq!(error, Some(io::ErrorKind::InvalidInput));
+    assert_eq!(port.baud_rate().err().map(|e| e.kind()), error);
+
+    port.set_baud_rate_custom(Direction::Both, 115200).unwrap();
+    let settings = port.settings().unwrap();
+    assert_eq!(settings.baud_rate, (B115200, B115200));
+}
+
+#[test]
+fn decode_marked_input() {
+    use Event::*;
+
+    let mut decoder = Decoder::new();
+    let mut events = Vec::new();
+
+    decoder.decode(b"a\xFF\xFFb\xFF\x00\x00c", false, &mut events);
+    assert_eq!(events, [Data(b'a'), Data(0xFF), Data(b'b'), Break, Data(b'c')]);
+
+    // Escape sequences can be split across reads
+    events.clear();
+    decoder.decode(b"\xFF", false, &mut events);
+    decoder.decode(b"\x00", false, &mut events);
+    assert!(events.is_empty());
+    decoder.decode(b"\x00\xFF", false, &mut events);
+    decoder.decode(b"\xFF", false, &mut events);
+    assert_eq!(events, [Break, Data(0xFF)]);
+
+    // Corrupted bytes
+    events.clear();
+    decoder.decode(b"\xFF\x00x\xFF\x00\x00", false, &mut events);
+    assert_eq!(events, [FramingError, Break]);
+
+    events.clear();
+    decoder.decode(b"\xFF\x00xy", true, &mut events);
+    assert_eq!(events, [ParityError(b'x'), Data(b'y')]);
+}
+
 // XXX The PTY only seems to work with 8 data bits
 #[test]
 #[ignore]
 fn data_bits() {
     use DataBits::*;
 
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
@@ -107,36 +291,225 @@ fn data_bits() {
     }
 }
 
-// XXX Should opening a port twice be forbidden?
-// - AFAIK, opening a port twice is not possible in Windows, but it's possible on Linux
-// - FWIW, QSerialPort and minicom forbid this operation via lockfiles
 #[test]
-#[ignore]
+fn discard() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let mut tx = OpenOptions::new().write(true).open(tx).unwrap();
+    let mut rx = SerialPort::open(rx).unwrap();
+    rx.set_timeout(Some(Duration::milliseconds(100)));
+
+    tx.write_all(b"stale").unwrap();
+    thread::sleep_ms(100);
+    rx.discard(Direction::Input).unwrap();
+
+    let mut buf = [0; 16];
+    assert_eq!(rx.read(&mut buf).err().map(|e| e.kind()), Some(io::ErrorKind::TimedOut));
+
+    tx.write_all(MESSAGE.as_bytes()).unwrap();
+    assert_eq!(rx.read(&mut buf).ok(), Some(MESSAGE.len()));
+    assert_eq!(str::from_utf8(&buf[..MESSAGE.len()]).ok(), Some(MESSAGE));
+}
+
+#[test]
+fn drain() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let mut tx = OpenOptions::new().write(true).open(tx).unwrap();
+    let mut rx = SerialPort::open(rx).unwrap();
+
+    assert!(!tx.drain_on_flush());
+    tx.set_drain_on_flush(true);
+
+    tx.write_all(MESSAGE.as_bytes()).unwrap();
+    tx.flush().unwrap();
+    tx.drain().unwrap();
+
+    let mut buf = [0; 16];
+    assert_eq!(rx.read(&mut buf).ok(), Some(MESSAGE.len()));
+}
+
+#[test]
 fn double_open() {
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
 
     let mut opts = OpenOptions::new();
-    opts.write(true);
+    opts.write(true).exclusive(true);
     let first = opts.open(port);
     let second = opts.open(port);
 
     assert!(first.is_ok() && second.is_err());
 }
 
+#[test]
+fn enumerate_ports() {
+    let pid = unsafe { libc::getpid() };
+    let sys = env::temp_dir().join(&format!("serial-rs-sysfs-{}", pid));
+    fake_sysfs(&sys);
+
+    let dev = Path::new("/dev");
+    let ports = enumerate::ports(&sys, dev).unwrap();
+
+    fs::remove_dir_all(&sys).unwrap();
+
+    assert_eq!(ports, [
+        PortInfo {
+            driver: None,
+            path: dev.join("ptyp0"),
+            port_type: PortType::Pty,
+            usb: None,
+        },
+        PortInfo {
+            driver: Some("cdc_acm".to_string()),
+            path: dev.join("ttyACM0"),
+            port_type: PortType::Usb,
+            usb: Some(UsbInfo {
+                interface: Some(0),
+                manufacturer: Some("Arduino (www.arduino.cc)".to_string()),
+                pid: 0x0043,
+                product: None,
+                serial_number: None,
+                vid: 0x2341,
+            }),
+        },
+        PortInfo {
+            driver: Some("serial".to_string()),
+            path: dev.join("ttyS0"),
+            port_type: PortType::Platform,
+            usb: None,
+        },
+        PortInfo {
+            driver: Some("ftdi_sio".to_string()),
+            path: dev.join("ttyUSB0"),
+            port_type: PortType::Usb,
+            usb: Some(UsbInfo {
+                interface: Some(0),
+                manufacturer: Some("FTDI".to_string()),
+                pid: 0x6001,
+                product: Some("FT232R USB UART".to_string()),
+                serial_number: Some("A6008isP".to_string()),
+                vid: 0x0403,
+            }),
+        },
+    ]);
+
+    let ftdi = ports[3].usb.as_ref().unwrap();
+    assert!(ftdi.matches(0x0403, 0x6001, None));
+    assert!(ftdi.matches(0x0403, 0x6001, Some("A6008isP")));
+    assert!(!ftdi.matches(0x0403, 0x6001, Some("A600ZZZZ")));
+    assert!(!ftdi.matches(0x0403, 0x6015, None));
+}
+
+#[cfg(feature = "evented")]
+#[test]
+fn evented_loopback() {
+    use evented::{AsyncPort, Reactor, Ready};
+
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let (tx_, rx_) = (tx.display(), rx.display());
+    let tx = match OpenOptions::new().write(true).open(tx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", tx_, e),
+        Ok(port) => AsyncPort::new(port).unwrap(),
+    };
+    let mut rx = match SerialPort::open(rx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", rx_, e),
+        Ok(port) => AsyncPort::new(port).unwrap(),
+    };
+    let mut tx = Some(tx);
+
+    let reactor = Reactor::new().unwrap();
+    let readable = Ready { readable: true, writable: false };
+    let writable = Ready { readable: false, writable: true };
+    reactor.register(&rx, 0, readable).unwrap();
+    reactor.register(tx.as_ref().unwrap(), 1, writable).unwrap();
+
+    let mut buf = [0; 16];
+    assert_eq!(rx.try_read(&mut buf).ok(), Some(None));
+    match rx.read(&mut buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+        result => panic!("{:?}: expected the read to block - got {:?}", rx_, result),
+    }
+
+    let mut received = Vec::new();
+    let mut events = Vec::new();
+    while received.len() < MESSAGE.len() {
+        events.clear();
+        if reactor.poll(&mut events, Some(Duration::seconds(1))).unwrap() == 0 {
+            panic!("{:?}: timed out waiting for events", rx_)
+        }
+
+        for &(token, ready) in &events {
+            match token {
+                0 if ready.readable => if let Some(n) = rx.try_read(&mut buf).unwrap() {
+                    received.extend(buf[..n].iter().cloned());
+                },
+                1 if ready.writable => {
+                    let mut port = tx.take().unwrap();
+                    assert_eq!(port.try_write(MESSAGE.as_bytes()).ok(), Some(Some(MESSAGE.len())));
+                    reactor.deregister(&port).unwrap();
+                },
+                _ => {},
+            }
+        }
+    }
+
+    assert_eq!(str::from_utf8(&received).ok(), Some(MESSAGE));
+
+    // The setters reconfigure the port without leaving non-blocking mode
+    assert!(rx.set_baud_rate(Direction::Both, B9600).is_ok());
+    assert_eq!(rx.settings().ok().map(|settings| settings.baud_rate), Some((B9600, B9600)));
+    assert!(rx.set_stop_bits(StopBits::Two).is_ok());
+    assert_eq!(rx.settings().ok().map(|settings| settings.stop_bits), Some(StopBits::Two));
+    match rx.read(&mut buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+        result => panic!("{:?}: expected the read to block - got {:?}", rx_, result),
+    }
+
+    // Writes block while the RS-485 mode is emulated
+    let mut port = rx.into_inner().unwrap();
+    port.rs485 = Some(Rs485Config::new());
+    match AsyncPort::new(port) {
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {},
+        Err(e) => panic!("{:?}: expected an InvalidInput error - got {:?}", rx_, e),
+        Ok(_) => panic!("{:?}: expected an InvalidInput error", rx_),
+    }
+}
+
 #[test]
 fn flow_control() {
     use FlowControl::*;
 
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
         Ok(port) => port,
     };
 
-    for &flow in &[Hardware, None, Software] {
+    let output_only = SoftwareFlowControl {
+        any: true,
+        input: false,
+        xoff: b'S',
+        xon: b'Q',
+        .. SoftwareFlowControl::new()
+    };
+    let input_only = SoftwareFlowControl {
+        output: false,
+        .. SoftwareFlowControl::new()
+    };
+    let flows = [
+        Both(output_only),
+        Hardware,
+        None,
+        Software(SoftwareFlowControl::new()),
+        Software(output_only),
+        Software(input_only),
+    ];
+
+    for &flow in &flows {
         if let Err(e) =  port.set_flow_control(flow) {
             panic!("{:?}: Couldn't set flow control to {:?} ({:?})", port_, flow, e)
         }
@@ -155,8 +528,8 @@ fn flow_control() {
 fn input_baud_rate() {
     use Direction::Input;
 
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
@@ -183,10 +556,126 @@ fn input_baud_rate() {
     }
 }
 
+#[test]
+fn line_counters() {
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let port_ = port.display();
+    let port = match SerialPort::open(port) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
+        Ok(port) => port,
+    };
+
+    let none = ModemMask { cd: false, cts: false, dsr: false, ri: false };
+    match port.wait_for_modem_change(none, None) {
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {},
+        result => panic!("{:?}: expected an empty mask to be rejected - got {:?}", port_, result),
+    }
+
+    // XXX PTY drivers don't keep interrupt counters
+    if port.line_counters().is_err() {
+        return
+    }
+
+    let mask = ModemMask { cd: true, cts: true, dsr: true, ri: true };
+    match port.wait_for_modem_change(mask, Some(Duration::milliseconds(50))) {
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {},
+        result => panic!("{:?}: expected a timeout - got {:?}", port_, result),
+    }
+}
+
+#[test]
+fn line_reader() {
+    let pair = virtual_pair().unwrap();
+    let (mut port, mut other) = pair.open().unwrap();
+
+    // Reads waiting for more bytes than a line has don't block past the timeout
+    let mode = BlockingMode { bytes: 64, deciseconds: 0 };
+    port.set_blocking_mode(mode).unwrap();
+
+    let mut reader = LineReader::new(port, Terminator::CrLf).unwrap();
+    let mut line = Vec::new();
+    reader.set_timeout(Some(Duration::milliseconds(200)));
+
+    other.write_all(b"OK\r\nERROR\r\n+CSQ: 1").unwrap();
+
+    assert_eq!(reader.read_line(&mut line).ok(), Some(4));
+    assert_eq!(reader.read_line(&mut line).ok(), Some(7));
+    assert_eq!(&line[..], b"OK\r\nERROR\r\n");
+
+    // The partial line is handed out on timeout
+    line.clear();
+    let e = reader.read_line(&mut line).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(&line[..], b"+CSQ: 1");
+
+    // Long lines are split
+    line.clear();
+    reader.set_max_len(4);
+    other.write_all(b"READY\r\n").unwrap();
+    let e = reader.read_line(&mut line).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(reader.read_line(&mut line).ok(), Some(3));
+    assert_eq!(&line[..], b"READY\r\n");
+
+    line.clear();
+    reader.set_terminator(Terminator::Custom(b"\x03".to_vec()));
+    other.write_all(b"\x02a\x03").unwrap();
+    assert_eq!(reader.read_line(&mut line).ok(), Some(3));
+    assert_eq!(&line[..], b"\x02a\x03");
+
+    assert_eq!(reader.into_inner().unwrap().blocking_mode().ok(), Some(mode));
+}
+
+#[test]
+fn lock_file() {
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let pid = unsafe { libc::getpid() };
+    let dir = env::temp_dir().join(&format!("serial-rs-lock-{}", pid));
+    let lock = dir.join(&format!("LCK..{}", port.file_name().unwrap().to_str().unwrap()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut opts = OpenOptions::new();
+    opts.lock_dir(&dir);
+
+    {
+        let first = opts.open(port);
+        let second = opts.open(port);
+
+        assert!(first.is_ok() && second.is_err());
+        assert!(fs::metadata(&lock).is_ok());
+    }
+
+    // The lock is released when the port is closed
+    assert!(fs::metadata(&lock).is_err());
+
+    // Stale locks are replaced
+    File::create(&lock).and_then(|mut file| write!(file, "{:>10}\n", i32::MAX)).unwrap();
+    assert!(opts.open(port).is_ok());
+
+    // Threads locking different ports don't trip over each other's temporary files
+    let threads = (0..8).map(|i| {
+        let dir = dir.clone();
+
+        thread::spawn(move || {
+            for _ in 0..100 {
+                LockFile::create(&dir, Path::new(&format!("/dev/ttyS{}", i))).unwrap();
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
 #[test]
 fn loopback() {
-    let socat = Socat::new();
-    let (tx, rx) = socat.ports();
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
     let (tx_, rx_) = (tx.display(), rx.display());
     let mut tx = match OpenOptions::new().write(true).open(tx) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", tx_, e),
@@ -215,10 +704,152 @@ fn loopback() {
     }
 }
 
+#[test]
+fn marked_input() {
+    use Event::*;
+
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let (tx_, rx_) = (tx.display(), rx.display());
+    let mut tx = match OpenOptions::new().write(true).open(tx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", tx_, e),
+        Ok(port) => port,
+    };
+    let mut rx = match SerialPort::open(rx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", rx_, e),
+        Ok(port) => port,
+    };
+
+    if let Err(e) = rx.set_marked_input(true) {
+        panic!("{:?}: Couldn't enable marked input ({:?})", rx_, e)
+    }
+    assert_eq!(rx.marked_input().ok(), Some(true));
+
+    let message = b"\x00\xFF!";
+    if let Err(e) = tx.write_all(message) {
+        panic!("{:?}: Couldn't send message ({:?})", tx_, e)
+    }
+
+    let mut events = Vec::new();
+    while events.len() < message.len() {
+        if let Err(e) = rx.read_events(&mut events) {
+            panic!("{:?}: Couldn't read events ({:?})", rx_, e)
+        }
+    }
+
+    assert_eq!(events, [Data(0x00), Data(0xFF), Data(b'!')]);
+}
+
+#[test]
+fn mock_port() {
+    let mut buf = [0; 16];
+    let mut mock = MockPort::new();
+    mock.
+        expect_write(b"AT\r").
+        push_delay(Duration::milliseconds(10)).
+        push_read(b"OK\r").
+        push_error(io::Error::new(io::ErrorKind::BrokenPipe, "unplugged"));
+
+    assert_eq!(query(&mut mock, b"AT\r", &mut buf).ok(), Some(3));
+    assert_eq!(&buf[..3], b"OK\r");
+    assert_eq!(mock.changes(), [
+        ConfigChange::BaudRate(Direction::Both, B115200),
+        ConfigChange::Timeout(Some(Duration::seconds(1))),
+    ]);
+    assert_eq!(mock.baud_rate().ok(), Some((B115200, B115200)));
+    assert!(!mock.is_done());
+
+    assert_eq!(mock.read(&mut buf).err().map(|e| e.kind()), Some(io::ErrorKind::BrokenPipe));
+    assert_eq!(mock.read(&mut buf).err().map(|e| e.kind()), Some(io::ErrorKind::TimedOut));
+    mock.verify();
+
+    // The same code drives a real port
+    let pair = virtual_pair().unwrap();
+    let (mut port, mut device) = pair.open().unwrap();
+    device.write_all(b"OK\r").unwrap();
+    assert_eq!(query(&mut port, b"AT\r", &mut buf).ok(), Some(3));
+    assert_eq!(&buf[..3], b"OK\r");
+}
+
+#[test]
+fn modem_lines() {
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let port_ = port.display();
+    let mut port = match OpenOptions::new().read(true).write(true).open(port) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
+        Ok(port) => port,
+    };
+
+    // XXX Most PTY drivers don't implement the modem control lines
+    if port.modem_status().is_err() {
+        return
+    }
+
+    for &(rts, dtr) in &[(true, true), (true, false), (false, true), (false, false)] {
+        if let Err(e) = port.set_rts_dtr(rts, dtr) {
+            panic!("{:?}: Couldn't drive RTS/DTR to {:?} ({:?})", port_, (rts, dtr), e)
+        }
+        let got = match port.modem_status() {
+            Err(e) => panic!("{:?}: Couldn't read modem status ({:?})", port_, e),
+            Ok(status) => (status.rts, status.dtr),
+        };
+
+        if (rts, dtr) != got {
+            panic!("{:?}: set {:?} - got {:?}", port_, (rts, dtr), got)
+        }
+    }
+
+    for &level in &[true, false] {
+        port.set_rts(level).unwrap();
+        port.set_dtr(!level).unwrap();
+
+        let status = port.modem_status().unwrap();
+        assert_eq!((status.rts, status.dtr), (level, !level));
+    }
+}
+
+#[test]
+fn nonblocking() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let (tx_, rx_) = (tx.display(), rx.display());
+    let mut tx = match OpenOptions::new().write(true).open(tx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", tx_, e),
+        Ok(port) => port,
+    };
+    let mut rx = match OpenOptions::new().nonblocking(true).open(rx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", rx_, e),
+        Ok(port) => port,
+    };
+
+    let mut buf = [0; 16];
+    match rx.read(&mut buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+        result => panic!("{:?}: expected `WouldBlock` - got {:?}", rx_, result),
+    }
+    assert_eq!(rx.poll_readable(Some(Duration::milliseconds(50))).ok(), Some(false));
+    assert_eq!(tx.poll_writable(Some(Duration::milliseconds(50))).ok(), Some(true));
+
+    if let Err(e) = tx.write_all(MESSAGE.as_bytes()) {
+        panic!("{:?}: Couldn't send message ({:?})", tx_, e)
+    }
+
+    assert_eq!(rx.poll_readable(Some(Duration::seconds(1))).ok(), Some(true));
+    match rx.read(&mut buf) {
+        Err(e) => panic!("{:?}: Couldn't read ({:?})", rx_, e),
+        Ok(n) => assert!(n > 0),
+    }
+
+    if let Err(e) = rx.set_nonblocking(false) {
+        panic!("{:?}: Couldn't disable non-blocking mode ({:?})", rx_, e)
+    }
+}
+
 #[test]
 fn open() {
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
 
     for &read in &[true, false] {
@@ -236,12 +867,36 @@ fn open() {
     }
 }
 
+#[test]
+fn open_with_settings() {
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let port_ = port.display();
+
+    let mut settings = PortSettings::new();
+    settings.
+        set_baud_rate(Direction::Both, B115200).
+        set_blocking_mode(BlockingMode { bytes: 0, deciseconds: 5 });
+
+    let port = match OpenOptions::new().settings(settings).open(port) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
+        Ok(port) => port,
+    };
+
+    match port.settings() {
+        Err(e) => panic!("{:?}: Couldn't read settings ({:?})", port_, e),
+        Ok(got) => if settings != got {
+            panic!("{:?}: set {:?} - got {:?}", port_, settings, got)
+        },
+    }
+}
+
 #[test]
 fn output_baud_rate() {
     use Direction::Output;
 
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
@@ -269,15 +924,15 @@ fn output_baud_rate() {
 fn parity() {
     use Parity::*;
 
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
         Ok(port) => port,
     };
 
-    for &parity in &[Even, None, Odd] {
+    for &parity in &[Even, Mark, None, Odd, Space] {
         if let Err(e) = port.set_parity(parity) {
             panic!("{:?}: Couldn't set parity to {:?} ({:?})", port_, parity, e)
         }
@@ -292,21 +947,508 @@ fn parity() {
     }
 }
 
+#[test]
+fn pcap_export() {
+    let mut buf = [0; 16];
+    let mut mock = MockPort::new();
+    mock.expect_write(b"AT\r").push_read(b"OK\r");
+
+    let start = Duration::seconds(1_000_000_000);
+    let pcap = PcapWriter::new(Vec::new(), start).unwrap();
+    let mut recording = RecordingPort::with_sink(mock, pcap);
+    assert_eq!(query(&mut recording, b"AT\r", &mut buf).ok(), Some(3));
+    let pcap = recording.into_inner().1.into_inner();
+
+    // Global header, then one packet per chunk, configuration changes are left out
+    assert_eq!(pcap.len(), 24 + 2 * (16 + 4));
+    assert_eq!(&pcap[..4], &[0xD4, 0xC3, 0xB2, 0xA1]);
+    assert_eq!(&pcap[20..24], &[147, 0, 0, 0]);
+
+    let (sent, received) = (&pcap[24..44], &pcap[44..]);
+    assert_eq!(&sent[..4], &[0x00, 0xCA, 0x9A, 0x3B]);
+    assert_eq!(&sent[8..16], &[4, 0, 0, 0, 4, 0, 0, 0]);
+    assert_eq!(&sent[16..], b"\x01AT\r");
+    assert_eq!(&received[16..], b"\x00OK\r");
+
+    // Captures convert to the same packets
+    let mut mock = MockPort::new();
+    mock.expect_write(b"AT\r").push_read(b"OK\r");
+    let mut recording = RecordingPort::new(mock, Vec::new()).unwrap();
+    query(&mut recording, b"AT\r", &mut buf).unwrap();
+    let capture = recording.into_inner().1.into_inner();
+
+    let converted = capture_to_pcap(&capture[..], Vec::new()).unwrap();
+    assert_eq!(converted.len(), pcap.len());
+    assert_eq!(&converted[..24], &pcap[..24]);
+    assert_eq!(&converted[40..44], &sent[16..]);
+}
+
+#[test]
+fn port_events() {
+    let pid = unsafe { libc::getpid() };
+    let sys = env::temp_dir().join(&format!("serial-rs-uevent-{}", pid));
+    fake_sysfs(&sys);
+
+    let dev = Path::new("/dev");
+    let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/ttyUSB0/tty/ttyUSB0";
+    let uevent = |action| Uevent {
+        action: action,
+        devname: Some("ttyUSB0"),
+        devpath: devpath,
+        subsystem: Some("tty"),
+    };
+
+    let added = monitor::event(&uevent("add"), &sys, dev);
+    let removed = monitor::event(&uevent("remove"), &sys, dev);
+    let changed = monitor::event(&uevent("change"), &sys, dev);
+
+    fs::remove_dir_all(&sys).unwrap();
+
+    match added {
+        Some(PortEvent::Added(ref port)) if port.path == dev.join("ttyUSB0") => {},
+        event => panic!("expected the addition of ttyUSB0 - got {:?}", event),
+    }
+    assert_eq!(removed, Some(PortEvent::Removed(dev.join("ttyUSB0"))));
+    assert_eq!(changed, None);
+
+    // Virtual consoles and non tty devices are ignored
+    let console = Uevent {
+        action: "remove",
+        devname: Some("tty5"),
+        devpath: "/devices/virtual/tty/tty5",
+        subsystem: Some("tty"),
+    };
+    let interface = Uevent {
+        action: "remove",
+        devname: None,
+        devpath: "/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0",
+        subsystem: Some("usb"),
+    };
+    assert_eq!(monitor::event(&console, &sys, dev), None);
+    assert_eq!(monitor::event(&interface, &sys, dev), None);
+}
+
+#[test]
+fn parse_uevent() {
+    let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/ttyUSB0/tty/ttyUSB0";
+    let buf = format!(
+        "remove@{0}\0ACTION=remove\0DEVPATH={0}\0SUBSYSTEM=tty\0MAJOR=188\0MINOR=0\0\
+         DEVNAME=ttyUSB0\0SEQNUM=2461\0",
+        devpath
+    );
+
+    assert_eq!(monitor::parse(buf.as_bytes()), Some(Uevent {
+        action: "remove",
+        devname: Some("ttyUSB0"),
+        devpath: devpath,
+        subsystem: Some("tty"),
+    }));
+
+    // Messages rebroadcast by udev are not kernel uevents
+    assert_eq!(monitor::parse(b"libudev\0\xFE\xED\xCA\xFE"), None);
+    assert_eq!(monitor::parse(b""), None);
+}
+
+#[test]
+fn pty_pair() {
+    let pair = virtual_pair().unwrap();
+    let (mut a, mut b) = pair.open().unwrap();
+    let mut buf = [0; 16];
+
+    a.write_all(MESSAGE.as_bytes()).unwrap();
+    assert_eq!(b.read(&mut buf).ok(), Some(MESSAGE.len()));
+
+    b.write_all(MESSAGE.as_bytes()).unwrap();
+    assert_eq!(a.read(&mut buf).ok(), Some(MESSAGE.len()));
+    assert_eq!(str::from_utf8(&buf[..MESSAGE.len()]).ok(), Some(MESSAGE));
+
+    // Dropping the pair hangs up the ports
+    drop(pair);
+    assert!(a.read(&mut buf).map(|n| n == 0).unwrap_or(true));
+}
+
+#[test]
+fn raw_fd() {
+    let pair = virtual_pair().unwrap();
+    let port = SerialPort::open(pair.ports().0).unwrap();
+    let settings = port.settings().unwrap();
+
+    let fd = port.into_raw_fd();
+    let port = unsafe { SerialPort::from_raw_fd(fd) };
+
+    assert_eq!(port.as_raw_fd(), fd);
+    assert_eq!(port.settings().ok(), Some(settings));
+}
+
 #[test]
 fn read_in_write_only_mode() {
-    let socat = Socat::new();
-    let mut port = OpenOptions::new().write(true).open(socat.ports().0).unwrap();
+    let pair = virtual_pair().unwrap();
+    let mut port = OpenOptions::new().write(true).open(pair.ports().0).unwrap();
     let mut buf = Vec::new();
 
     assert!(port.read_to_end(&mut buf).is_err())
 }
 
+#[test]
+fn record_replay() {
+    let mut buf = [0; 16];
+    let mut mock = MockPort::new();
+    mock.expect_write(b"AT\r").push_read(b"OK\r");
+
+    let mut recording = RecordingPort::new(mock, Vec::new()).unwrap();
+    assert_eq!(query(&mut recording, b"AT\r", &mut buf).ok(), Some(3));
+    let (mock, capture) = recording.into_inner();
+    let capture = capture.into_inner();
+    mock.verify();
+
+    let reader = CaptureReader::new(&capture[..]).unwrap();
+    assert!(reader.start_time() > Duration::zero());
+    let records: Vec<_> = reader.map(|record| record.unwrap().1).collect();
+    assert_eq!(records, [
+        Record::Config(ConfigChange::BaudRate(Direction::Both, B115200)),
+        Record::Config(ConfigChange::Timeout(Some(Duration::seconds(1)))),
+        Record::Write(b"AT\r".to_vec()),
+        Record::Read(b"OK\r".to_vec()),
+    ]);
+
+    // The replayed session behaves like the recorded one
+    let mut replay = ReplayPort::new(&capture[..], false).unwrap();
+    assert_eq!(query(&mut replay, b"AT\r", &mut buf).ok(), Some(3));
+    assert_eq!(&buf[..3], b"OK\r");
+    replay.verify();
+
+    // Truncated captures are rejected
+    assert!(ReplayPort::new(&capture[..capture.len() - 1], false).is_err());
+    assert!(CaptureReader::new(&b"garbage"[..]).is_err());
+
+    // So are records with a corrupted length, here a read of 2^64 - 1 bytes
+    let mut corrupted = capture[..16].to_vec();
+    corrupted.extend([0, 0].iter().cloned());
+    corrupted.extend(iter::repeat(0xFF).take(9));
+    corrupted.push(1);
+    let mut reader = CaptureReader::new(&corrupted[..]).unwrap();
+    let error = reader.next().and_then(|record| record.err()).map(|e| e.kind());
+    assert_eq!(error, Some(io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn reconnect() {
+    let pid = unsafe { libc::getpid() };
+    let dir = env::temp_dir().join(&format!("serial-rs-reconnect-{}", pid));
+    let link = dir.join("ttyUSB0");
+    fs::create_dir_all(&dir).unwrap();
+
+    let first = virtual_pair().unwrap();
+    fs::soft_link(first.ports().0, &link).unwrap();
+
+    let mut port = ReconnectingPort::open(&OpenOptions::new(), &link).unwrap();
+    let (tx, events) = mpsc::channel();
+    port.on_event(move |event| {
+        tx.send(match *event {
+            LinkEvent::Disconnected(_) => "disconnected",
+            LinkEvent::Failed(..) => "failed",
+            LinkEvent::Reconnected(_) => "reconnected",
+        }).unwrap()
+    });
+    port.set_retry(3, Duration::milliseconds(100));
+    port.configure(|port| {
+        port.set_timeout(Some(Duration::milliseconds(100)));
+        port.set_baud_rate(Direction::Both, B57600)
+    }).unwrap();
+
+    // Unplug the device and plug it back
+    drop(first);
+    let second = virtual_pair().unwrap();
+    fs::remove_file(&link).unwrap();
+    fs::soft_link(second.ports().0, &link).unwrap();
+
+    // The lost device is replaced, then the read is retried and times out as nothing was sent
+    let mut buf = [0; 16];
+    let timed_out = port.read(&mut buf).err().map(|e| e.kind());
+    let events: Vec<_> = iter::repeat(()).map(|_| events.try_recv()).
+        take_while(|event| event.is_ok()).
+        map(|event| event.unwrap()).
+        collect();
+    let baud_rate = port.get_ref().unwrap().baud_rate().ok();
+
+    let mut tx = OpenOptions::new().write(true).open(second.ports().1).unwrap();
+    tx.write_all(MESSAGE.as_bytes()).unwrap();
+    let mut received = vec![0; MESSAGE.len()];
+    let read = port.read(&mut received);
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(timed_out, Some(io::ErrorKind::TimedOut));
+    assert_eq!(events, ["disconnected", "reconnected"]);
+    assert_eq!(baud_rate, Some((B57600, B57600)));
+    assert_eq!(read.ok(), Some(MESSAGE.len()));
+    assert_eq!(str::from_utf8(&received).ok(), Some(MESSAGE));
+
+    // XXX The pair keeps the pty open, so `TIOCEXCL` outlives the port and only root can reopen
+    // it
+    if unsafe { libc::geteuid() } != 0 {
+        return
+    }
+
+    // The old device is closed first, so a device opened in exclusive mode can be reopened
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).exclusive(true);
+    let mut port = ReconnectingPort::open(&options, second.ports().0).unwrap();
+    port.set_retry(1, Duration::zero());
+    assert!(port.reconnect().is_ok());
+    assert!(port.get_ref().is_some());
+}
+
+#[test]
+fn rs485() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let mut tx = OpenOptions::new().read(true).write(true).open(tx).unwrap();
+    let mut rx = SerialPort::open(rx).unwrap();
+    let config = Rs485Config {
+        delay_before_send: Duration::milliseconds(1),
+        rts_after_send: true,
+        rts_on_send: false,
+        .. Rs485Config::new()
+    };
+
+    // XXX Most PTY drivers implement neither the RS-485 mode nor the modem control lines
+    if tx.set_rs485(config).is_err() {
+        return
+    }
+
+    assert_eq!(tx.rs485().ok(), Some(config));
+
+    tx.write_all(MESSAGE.as_bytes()).unwrap();
+    let mut buf = vec![0; MESSAGE.len()];
+    assert_eq!(rx.read(&mut buf).ok(), Some(MESSAGE.len()));
+    assert_eq!(str::from_utf8(&buf).ok(), Some(MESSAGE));
+
+    // The bus is released after the transmission
+    assert_eq!(tx.modem_status().ok().map(|status| status.rts), Some(true));
+
+    tx.set_rs485(Rs485Config::disabled()).unwrap();
+    assert_eq!(tx.rs485().ok().map(|config| config.enabled), Some(false));
+}
+
+#[test]
+fn rs485_emulation() {
+    struct Bus {
+        log: Vec<String>,
+        queue: Vec<u8>,
+        reply: Vec<u8>,
+        unread: Vec<u8>,
+    }
+
+    impl Transceiver for Bus {
+        fn bytes_to_read(&self) -> io::Result<usize> {
+            Ok(self.queue.len())
+        }
+
+        fn drain(&mut self) -> io::Result<()> {
+            // The reply arrives once the frame is out, while waiting `delay_after_send`
+            self.queue.extend(self.reply.iter().cloned());
+            self.reply.clear();
+            self.log.push("drain".to_string());
+            Ok(())
+        }
+
+        fn read_received(&mut self, n: usize) -> io::Result<Vec<u8>> {
+            let data = self.queue[..n].to_vec();
+            self.queue = self.queue[n..].to_vec();
+            Ok(data)
+        }
+
+        fn set_rts(&mut self, level: bool) -> io::Result<()> {
+            self.log.push(format!("rts {}", level));
+            Ok(())
+        }
+
+        fn transmit(&mut self, buf: &[u8]) -> io::Result<()> {
+            // The transceiver echoes the frame
+            self.queue.extend(buf.iter().cloned());
+            self.log.push(format!("write {}", str::from_utf8(buf).unwrap()));
+            Ok(())
+        }
+
+        fn unread(&mut self, data: Vec<u8>) {
+            self.unread.extend(data.into_iter());
+        }
+    }
+
+    let bus = || Bus {
+        log: Vec::new(),
+        queue: b"x".to_vec(),
+        reply: b"OK".to_vec(),
+        unread: Vec::new(),
+    };
+    let config = Rs485Config::new();
+
+    // Only the echo is dropped, earlier data and the reply are kept
+    let mut port = bus();
+    assert_eq!(rs485::write(&mut port, b"abc", &config).ok(), Some(3));
+    assert_eq!(port.log, ["rts true", "write abc", "drain", "rts false"]);
+    assert_eq!(&port.unread[..], b"x");
+    assert_eq!(&port.queue[..], b"OK");
+
+    let mut port = bus();
+    let config = Rs485Config { rx_during_tx: true, .. config };
+    assert_eq!(rs485::write(&mut port, b"abc", &config).ok(), Some(3));
+    assert_eq!(&port.unread[..], b"");
+    assert_eq!(&port.queue[..], b"xabcOK");
+
+    // The halves of a split port wouldn't drive RTS
+    let pair = virtual_pair().unwrap();
+    let mut port = SerialPort::open(pair.ports().0).unwrap();
+    port.rs485 = Some(config);
+    assert_eq!(port.split().err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+
+    // A clone would race for the echo
+    assert_eq!(port.try_clone().err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn send_break() {
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let port_ = port.display();
+    let mut port = match OpenOptions::new().write(true).open(port) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
+        Ok(port) => port,
+    };
+
+    if let Err(e) = port.send_break(Duration::milliseconds(10)) {
+        panic!("{:?}: Couldn't send break ({:?})", port_, e)
+    }
+}
+
+#[test]
+fn send_xon_xoff() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let mut tx = OpenOptions::new().write(true).open(tx).unwrap();
+    let mut rx = SerialPort::open(rx).unwrap();
+    let mut flow = SoftwareFlowControl::new();
+    flow.xoff = b'S';
+    flow.xon = b'Q';
+    tx.set_flow_control(FlowControl::Software(flow)).unwrap();
+
+    tx.send_xoff().unwrap();
+    tx.send_xon().unwrap();
+    thread::sleep_ms(100);
+
+    let mut buf = [0; 2];
+    assert_eq!(rx.read(&mut buf).ok(), Some(2));
+    assert_eq!(&buf, b"SQ");
+}
+
+#[test]
+fn settings() {
+    use FlowControl::*;
+
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
+    let port_ = port.display();
+    let mut port = match SerialPort::open(port) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
+        Ok(port) => port,
+    };
+
+    let flows = [Both(SoftwareFlowControl::new()), Hardware, None,
+                 Software(SoftwareFlowControl::new())];
+
+    for &flow in &flows {
+        let mut settings = PortSettings::new();
+        settings.
+            set_baud_rate(Direction::Input, B57600).
+            set_baud_rate(Direction::Output, B115200).
+            set_flow_control(flow);
+
+        if let Err(e) = port.apply_settings(&settings) {
+            panic!("{:?}: Couldn't apply settings {:?} ({:?})", port_, settings, e)
+        }
+        let got = match port.settings() {
+            Err(e) => panic!("{:?}: Couldn't read settings ({:?})", port_, e),
+            Ok(settings) => settings,
+        };
+
+        if settings != got {
+            panic!("{:?}: set {:?} - got {:?}", port_, settings, got)
+        }
+    }
+
+    // RTS/CTS combined with XON/XOFF set behind the back of the port isn't reported as either
+    let mut termios = port.fetch().unwrap();
+    termios.set(control::Flag::CRTSCTS);
+    termios.clear(input::Flag::IXOFF);
+    termios.set(input::Flag::IXON);
+    port.update(termios).unwrap();
+    match port.settings().map(|settings| settings.flow_control) {
+        Ok(Both(software)) => assert!(!software.input && software.output),
+        result => panic!("{:?}: expected both flow controls - got {:?}", port_, result),
+    }
+}
+
+#[test]
+fn split() {
+    let pair = virtual_pair().unwrap();
+    let (a, b) = pair.ports();
+    let (a_, b_) = (a.display(), b.display());
+    let mut a = match OpenOptions::new().read(true).write(true).open(a) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", a_, e),
+        Ok(port) => port,
+    };
+    let mut b = match OpenOptions::new().read(true).write(true).open(b) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", b_, e),
+        Ok(port) => port,
+    };
+
+    // Data the port took from the device but didn't hand out yet moves to the reader
+    a.unread = b">>".to_vec();
+    let (mut reader, mut writer) = match a.split() {
+        Err(e) => panic!("{:?}: Couldn't split ({:?})", a_, e),
+        Ok(halves) => halves,
+    };
+
+    // The reader blocks in its own thread, without blocking the writer
+    let receiver = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 16];
+
+        while buf.len() < MESSAGE.len() + 2 {
+            let n = reader.read(&mut chunk).unwrap();
+            buf.extend(chunk[..n].iter().cloned());
+        }
+
+        buf
+    });
+
+    writer.write_all(MESSAGE.as_bytes()).unwrap();
+
+    let mut buf = [0; 16];
+    let mut echo = Vec::new();
+    while echo.len() < MESSAGE.len() {
+        let n = b.read(&mut buf).unwrap();
+        echo.extend(buf[..n].iter().cloned());
+    }
+    b.write_all(&echo).unwrap();
+
+    let received = receiver.join().unwrap();
+    assert_eq!(&received[..2], b">>");
+    assert_eq!(str::from_utf8(&received[2..]).ok(), Some(MESSAGE));
+    assert_eq!(&a.unread[..], b"");
+
+    // The port is still usable to change the configuration
+    assert!(a.try_clone().unwrap().set_stop_bits(StopBits::Two).is_ok());
+    assert_eq!(a.stop_bits().ok(), Some(StopBits::Two));
+}
+
 #[test]
 fn stop_bits() {
     use StopBits::*;
 
-    let socat = Socat::new();
-    let port = socat.ports().0;
+    let pair = virtual_pair().unwrap();
+    let port = pair.ports().0;
     let port_ = port.display();
     let mut port = match SerialPort::open(port) {
         Err(e) => panic!("{:?}: Couldn't open ({:?})", port_, e),
@@ -328,10 +1470,83 @@ fn stop_bits() {
     }
 }
 
+#[test]
+fn timeout() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let (tx_, rx_) = (tx.display(), rx.display());
+    let mut tx = match OpenOptions::new().write(true).open(tx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", tx_, e),
+        Ok(port) => port,
+    };
+    let mut rx = match SerialPort::open(rx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", rx_, e),
+        Ok(port) => port,
+    };
+
+    let timeout = Duration::milliseconds(100);
+    rx.set_timeout(Some(timeout));
+    assert_eq!(rx.timeout(), Some(timeout));
+
+    let mut buf = [0; 16];
+    match rx.read(&mut buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {},
+        result => panic!("{:?}: expected a timeout - got {:?}", rx_, result),
+    }
+
+    if let Err(e) = tx.write_all(MESSAGE.as_bytes()) {
+        panic!("{:?}: Couldn't send message ({:?})", tx_, e)
+    }
+
+    match rx.read(&mut buf) {
+        Err(e) => panic!("{:?}: Couldn't read ({:?})", rx_, e),
+        Ok(n) => assert!(n > 0),
+    }
+
+    // Writes time out once the receiver stops draining the line
+    tx.set_timeout(Some(timeout));
+    let stalled = tx.write_all(&vec![0; 1 << 20]).err().map(|e| e.kind());
+    assert_eq!(stalled, Some(io::ErrorKind::TimedOut));
+
+    // Fractions of a millisecond are not truncated to a zero timeout
+    assert_eq!(poll::millis(Some(Duration::microseconds(500))), 1);
+    assert_eq!(poll::millis(Some(Duration::microseconds(1500))), 2);
+    assert_eq!(poll::millis(Some(Duration::milliseconds(2))), 2);
+    assert_eq!(poll::millis(Some(Duration::milliseconds(-5))), 0);
+    assert_eq!(poll::millis(None), -1);
+}
+
+#[test]
+fn write_address() {
+    let pair = virtual_pair().unwrap();
+    let (tx, rx) = pair.ports();
+    let (tx_, rx_) = (tx.display(), rx.display());
+    let mut tx = match OpenOptions::new().write(true).open(tx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", tx_, e),
+        Ok(port) => port,
+    };
+    let mut rx = match SerialPort::open(rx) {
+        Err(e) => panic!("{:?}: Couldn't open ({:?})", rx_, e),
+        Ok(port) => port,
+    };
+
+    let parity = tx.parity().unwrap();
+    if let Err(e) = tx.write_address(0x42) {
+        panic!("{:?}: Couldn't send address ({:?})", tx_, e)
+    }
+    assert_eq!(tx.parity().ok(), Some(parity));
+
+    let mut buf = [0];
+    match rx.read(&mut buf) {
+        Err(e) => panic!("{:?}: Couldn't read ({:?})", rx_, e),
+        Ok(n) => assert_eq!(&buf[..n], &[0x42]),
+    }
+}
+
 #[test]
 fn write_in_read_only_mode() {
-    let socat = Socat::new();
-    let mut port = SerialPort::open(socat.ports().0).unwrap();
+    let pair = virtual_pair().unwrap();
+    let mut port = SerialPort::open(pair.ports().0).unwrap();
 
     assert!(port.write_all(MESSAGE.as_bytes()).is_err())
 }